@@ -0,0 +1,622 @@
+use std::{collections::HashMap, fmt, net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket}, sync::{Arc, atomic::Ordering, mpsc::{SyncSender, TrySendError}}, time::{Duration, Instant}};
+
+use crate::{auth::Auth, osc::Command, ingest::Ingest, source::SourceId, status::Stats, rdm};
+
+pub const PORT: u16 = 6454;
+const ID: &[u8] = b"Art-Net\0";
+const OP_POLL: u16 = 0x2000;
+const OP_POLL_REPLY: u16 = 0x2100;
+const OP_DMX: u16 = 0x5000;
+const OP_SYNC: u16 = 0x5200;
+const OP_ADDRESS: u16 = 0x6000;
+const OP_TOD_REQUEST: u16 = 0x8000;
+const OP_TOD_DATA: u16 = 0x8100;
+const OP_TOD_CONTROL: u16 = 0x8200;
+const OP_RDM: u16 = 0x8300;
+const RDM_VERSION: u8 = 0x01;
+const ATC_FLUSH: u8 = 0x01;
+// UIDs in one ArtTodData
+const TOD_BLOCK: usize = 200;
+const PROTOCOL_VERSION: u16 = 14;
+const FIRMWARE_VERSION: u16 = 1;
+const SHORT_NAME_SIZE: usize = 18;
+const LONG_NAME_SIZE: usize = 64;
+// node report codes
+const RC_POWER_OK: u16 = 0x0001;
+const RC_DMX_ERROR: u16 = 0x0008;
+// sequence of a sender silent for that long starts over
+const SEQUENCE_RESET: Duration = Duration::from_secs(1);
+
+// 15 bit Port-Address: net (7 bits), sub-net (4 bits), universe (4 bits)
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PortAddress(u16);
+impl PortAddress {
+  // "<net>:<sub-net>:<universe>" or the whole Port-Address as a number
+  pub fn parse(s: &str) -> Option<PortAddress> {
+    let parts: Vec<&str> = s.split(':').collect();
+    match parts.as_slice() {
+      [a] => a.parse::<u16>().ok().filter(|a| *a < 0x8000).map(PortAddress),
+      [net, sub, uni] => {
+        let net = net.parse::<u16>().ok().filter(|n| *n < 0x80)?;
+        let sub = sub.parse::<u16>().ok().filter(|s| *s < 0x10)?;
+        let uni = uni.parse::<u16>().ok().filter(|u| *u < 0x10)?;
+        Some(PortAddress(net << 8 | sub << 4 | uni))
+      },
+      _ => None,
+    }
+  }
+  pub fn net(&self) -> u8 {
+    (self.0 >> 8) as u8
+  }
+  // sub-net in the high nibble, universe in the low one
+  pub fn sub_uni(&self) -> u8 {
+    self.0 as u8
+  }
+  // Applies ArtAddress switch fields: bit 7 set programs the value, 0 resets it to `default`, others keep it.
+  fn program(&self, net: u8, sub: u8, uni: u8, default: PortAddress) -> PortAddress {
+    let field = |v: u8, current: u16, default: u16, mask: u16| match v {
+      0 => default,
+      v if v & 0x80 != 0 => v as u16 & mask,
+      _ => current,
+    };
+    let net = field(net, self.0 >> 8, default.0 >> 8, 0x7f);
+    let sub = field(sub, self.0 >> 4 & 0xf, default.0 >> 4 & 0xf, 0xf);
+    let uni = field(uni, self.0 & 0xf, default.0 & 0xf, 0xf);
+    PortAddress(net << 8 | sub << 4 | uni)
+  }
+}
+impl fmt::Display for PortAddress {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}:{}:{}", self.net(), self.sub_uni() >> 4, self.sub_uni() & 0xf)
+  }
+}
+
+#[derive(Debug)]
+pub enum Packet<'a> {
+  Poll,
+  Sync,
+  Dmx { sequence: u8, port_address: PortAddress, data: &'a [u8] },
+  // empty names and 0x7f switches mean no change
+  Address { net: u8, sub: u8, uni: u8, short_name: String, long_name: String },
+  // low bytes of the requested Port-Addresses
+  TodRequest { net: u8, addresses: Vec<u8> },
+  TodControl { net: u8, flush: bool, address: u8 },
+  // RDM packet without the start code
+  Rdm { net: u8, address: u8, packet: &'a [u8] },
+}
+
+// Zero terminated string of a fixed size field.
+fn read_name(field: &[u8]) -> String {
+  let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+  String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+fn write_name(field: &mut [u8], name: &str) {
+  let bytes = name.as_bytes();
+  // keep the terminating zero
+  let n = bytes.len().min(field.len() - 1);
+  field[..n].copy_from_slice(&bytes[..n]);
+}
+
+// None for anything else than a valid Art-Net packet of supported type.
+pub fn parse(buf: &[u8]) -> Option<Packet<'_>> {
+  if buf.len() < 12 || &buf[..8] != ID {
+    return None;
+  }
+  let opcode = u16::from_le_bytes([buf[8], buf[9]]);
+  if u16::from_be_bytes([buf[10], buf[11]]) < PROTOCOL_VERSION {
+    return None;
+  }
+  match opcode {
+    OP_POLL => Some(Packet::Poll),
+    OP_SYNC => Some(Packet::Sync),
+    OP_DMX => {
+      if buf.len() < 18 {
+        return None;
+      }
+      let port_address = PortAddress(u16::from_le_bytes([buf[14], buf[15] & 0x7f]));
+      let length = u16::from_be_bytes([buf[16], buf[17]]) as usize;
+      if !(2..=512).contains(&length) || buf.len() < 18 + length {
+        return None;
+      }
+      Some(Packet::Dmx { sequence: buf[12], port_address, data: &buf[18..18+length] })
+    },
+    OP_ADDRESS => {
+      if buf.len() < 107 {
+        return None;
+      }
+      Some(Packet::Address {
+        net: buf[12],
+        sub: buf[104],
+        uni: buf[100],
+        short_name: read_name(&buf[14..14+SHORT_NAME_SIZE]),
+        long_name: read_name(&buf[32..32+LONG_NAME_SIZE]),
+      })
+    },
+    OP_TOD_REQUEST => {
+      let count = *buf.get(23)? as usize;
+      let addresses = buf.get(24..24+count.min(32))?.to_vec();
+      Some(Packet::TodRequest { net: buf[21] & 0x7f, addresses })
+    },
+    OP_TOD_CONTROL => {
+      if buf.len() < 24 {
+        return None;
+      }
+      Some(Packet::TodControl { net: buf[21] & 0x7f, flush: buf[22] == ATC_FLUSH, address: buf[23] })
+    },
+    // only ArProcess command
+    OP_RDM if buf.len() > 24 && buf[12] == RDM_VERSION && buf[22] == 0 => {
+      Some(Packet::Rdm { net: buf[21] & 0x7f, address: buf[23], packet: &buf[24..] })
+    },
+    _ => None,
+  }
+}
+
+fn header(opcode: u16) -> Vec<u8> {
+  let mut buf = ID.to_vec();
+  buf.extend_from_slice(&opcode.to_le_bytes());
+  buf.extend_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+  buf
+}
+
+// ArtTodData packets with the whole table.
+fn tod_data(port_address: PortAddress, tod: &[rdm::Uid]) -> Vec<Vec<u8>> {
+  let blocks: Vec<&[rdm::Uid]> = if tod.is_empty() { vec![&[]] } else { tod.chunks(TOD_BLOCK).collect() };
+  blocks.iter().enumerate().map(|(i, uids)| {
+    let mut buf = header(OP_TOD_DATA);
+    // RDM version, port, spare, bind index
+    buf.extend_from_slice(&[RDM_VERSION, 1, 0, 0, 0, 0, 0, 0, 1]);
+    // full table
+    buf.extend_from_slice(&[port_address.net(), 0x00, port_address.sub_uni()]);
+    buf.extend_from_slice(&(tod.len() as u16).to_be_bytes());
+    buf.extend_from_slice(&[i as u8, uids.len() as u8]);
+    for uid in uids.iter() {
+      buf.extend_from_slice(&uid.0);
+    }
+    buf
+  }).collect()
+}
+
+// ArtDmx with the universe, padded to even length.
+pub fn dmx_packet(port_address: PortAddress, sequence: u8, data: &[u8]) -> Vec<u8> {
+  let length = (data.len().max(2) + 1) & !1;
+  let mut buf = header(OP_DMX);
+  buf.extend_from_slice(&[sequence, 0, port_address.sub_uni(), port_address.net()]);
+  buf.extend_from_slice(&(length as u16).to_be_bytes());
+  buf.extend_from_slice(data);
+  buf.resize(18 + length, 0);
+  buf
+}
+
+fn rdm_packet(port_address: PortAddress, packet: &[u8]) -> Vec<u8> {
+  let mut buf = header(OP_RDM);
+  buf.extend_from_slice(&[RDM_VERSION, 0, 0, 0, 0, 0, 0, 0, 0]);
+  // ArProcess
+  buf.extend_from_slice(&[port_address.net(), 0x00, port_address.sub_uni()]);
+  buf.extend_from_slice(packet);
+  buf
+}
+
+pub enum RdmCommand {
+  // reply with the table of devices, discovering it first if `discover`
+  Tod { discover: bool },
+  Request(Vec<u8>),
+}
+
+// RDM work for the output loop, which owns the device.
+pub struct RdmRequest {
+  from: SocketAddr,
+  port_address: PortAddress,
+  command: RdmCommand,
+}
+
+// Runs RDM requests of the Art-Net controllers on the DMX line, between DMX frames.
+pub struct RdmPort {
+  sock: UdpSocket,
+  controller: rdm::Controller,
+  // waiting for the running discovery
+  waiting: Vec<(SocketAddr, PortAddress)>,
+}
+
+impl RdmPort {
+  pub fn new(sock: UdpSocket, controller: rdm::Controller) -> RdmPort {
+    RdmPort { sock, controller, waiting: Vec::new() }
+  }
+  fn send(&self, to: SocketAddr, buf: &[u8]) {
+    if let Err(e) = self.sock.send_to(buf, to) {
+      println!("Error sending RDM reply to {}: {}", to, e);
+    }
+  }
+  fn send_tod(&self, to: SocketAddr, port_address: PortAddress) {
+    for buf in tod_data(port_address, &self.controller.tod()) {
+      self.send(to, &buf);
+    }
+  }
+  pub fn handle(&mut self, bus: &mut dyn rdm::Bus, request: RdmRequest) -> Result<(), crate::ftd2xx::FTError> {
+    let to = SocketAddr::new(request.from.ip(), PORT);
+    match request.command {
+      RdmCommand::Tod { discover } => {
+        if discover || self.controller.is_discovering() {
+          if !self.controller.is_discovering() {
+            self.controller.start_discovery(bus, true)?;
+          }
+          self.waiting.push((to, request.port_address));
+        } else {
+          self.send_tod(to, request.port_address);
+        }
+      },
+      RdmCommand::Request(packet) => {
+        if let Some(response) = self.controller.request(bus, &packet)? {
+          self.send(to, &rdm_packet(request.port_address, &response));
+        }
+      },
+    }
+    Ok(())
+  }
+  // Continues discovery, call once per frame.
+  pub fn step(&mut self, bus: &mut dyn rdm::Bus) -> Result<(), crate::ftd2xx::FTError> {
+    if !self.controller.is_discovering() {
+      return Ok(());
+    }
+    if self.controller.step(bus)? {
+      println!("RDM discovery found {} devices", self.controller.tod().len());
+      for (to, port_address) in std::mem::take(&mut self.waiting) {
+        self.send_tod(to, port_address);
+      }
+    }
+    Ok(())
+  }
+  pub fn discover(&mut self, bus: &mut dyn rdm::Bus) -> Result<(), crate::ftd2xx::FTError> {
+    self.controller.start_discovery(bus, true)
+  }
+}
+
+// Drops ArtDmx packets arriving out of order. Sequence 0 means the sender doesn't use it.
+#[derive(Default)]
+struct Sequences {
+  last: HashMap<SocketAddr, (u8, Instant)>,
+}
+impl Sequences {
+  fn accept(&mut self, addr: SocketAddr, sequence: u8, now: Instant) -> bool {
+    if sequence == 0 {
+      return true;
+    }
+    if let Some((last, seen)) = self.last.get(&addr) {
+      let behind = last.wrapping_sub(sequence);
+      if behind < 128 && now.saturating_duration_since(*seen) < SEQUENCE_RESET {
+        return false;
+      }
+    }
+    self.last.insert(addr, (sequence, now));
+    true
+  }
+}
+
+// Art-Net node with one output port, listening for ArtDmx of its Port-Address,
+// every sender is a separate source. Answers ArtPoll, ArtAddress is accepted if enabled.
+pub struct Node {
+  sock: UdpSocket,
+  port_address: PortAddress,
+  // set on the command line, ArtAddress can reset to it
+  default_port_address: PortAddress,
+  short_name: String,
+  long_name: String,
+  serial: String,
+  // senders allowed to send ArtAddress, None if it's disabled
+  remote: Option<Vec<IpAddr>>,
+  // RDM requests for the output loop, None if RDM is disabled
+  rdm: Option<SyncSender<RdmRequest>>,
+  reports: u32,
+  // ArtSync is accepted only from the sender of ArtDmx
+  dmx_from: Option<IpAddr>,
+  size: usize,
+  // --allow list, packets of other senders are counted as rejected
+  auth: Auth,
+  ingest: Ingest,
+  sequences: Sequences,
+  stats: Arc<Stats>,
+}
+
+impl Node {
+  pub fn new(sock: UdpSocket, port_address: PortAddress, size: usize, auth: Auth, ingest: Ingest, stats: Arc<Stats>, serial: String) -> Node {
+    Node {
+      sock, port_address, size, auth, ingest, stats,
+      default_port_address: port_address,
+      short_name: "roscdmx".to_string(),
+      long_name: format!("roscdmx DMX USB {}", serial).trim_end().to_string(),
+      serial,
+      remote: None,
+      rdm: None,
+      reports: 0,
+      dmx_from: None,
+      sequences: Sequences::default(),
+    }
+  }
+  // Accepts ArtAddress from localhost and these addresses.
+  pub fn allow_remote(&mut self, admins: Vec<IpAddr>) {
+    self.remote = Some(admins);
+  }
+  pub fn enable_rdm(&mut self, requests: SyncSender<RdmRequest>) {
+    self.rdm = Some(requests);
+  }
+  fn rdm(&self, from: SocketAddr, command: RdmCommand) {
+    let request = RdmRequest { from, port_address: self.port_address, command };
+    if let Some(queue) = &self.rdm {
+      match queue.try_send(request) {
+        Ok(()) => {},
+        Err(TrySendError::Full(_)) => println!("RDM queue full, request from {} dropped", from),
+        Err(TrySendError::Disconnected(_)) => {
+          println!("Output loop has stopped");
+          std::process::exit(1);
+        },
+      }
+    }
+  }
+  fn is_ours(&self, net: u8, address: u8) -> bool {
+    net == self.port_address.net() && address == self.port_address.sub_uni()
+  }
+  // Address the sender reaches us on, for the reply.
+  fn local_ip(&self, to: SocketAddr) -> Ipv4Addr {
+    let bound = self.sock.local_addr().map(|a| a.ip());
+    if let Ok(IpAddr::V4(ip)) = bound {
+      if !ip.is_unspecified() {
+        return ip;
+      }
+    }
+    let probe = UdpSocket::bind("0.0.0.0:0").and_then(|s| s.connect(to).and(s.local_addr()));
+    match probe {
+      Ok(SocketAddr::V4(a)) => *a.ip(),
+      _ => Ipv4Addr::UNSPECIFIED,
+    }
+  }
+  fn poll_reply(&mut self, to: SocketAddr) -> Vec<u8> {
+    let open = self.stats.device_open.load(Ordering::Relaxed);
+    self.reports = self.reports.wrapping_add(1) % 10000;
+    let report = if open {
+      format!("#{:04x} [{:04}] {} ok", RC_POWER_OK, self.reports, self.serial)
+    } else {
+      format!("#{:04x} [{:04}] {} not writing", RC_DMX_ERROR, self.reports, self.serial)
+    };
+    let mut buf = vec![0u8; 239];
+    buf[..8].copy_from_slice(ID);
+    buf[8..10].copy_from_slice(&OP_POLL_REPLY.to_le_bytes());
+    buf[10..14].copy_from_slice(&self.local_ip(to).octets());
+    buf[14..16].copy_from_slice(&PORT.to_le_bytes());
+    buf[16..18].copy_from_slice(&FIRMWARE_VERSION.to_be_bytes());
+    buf[18] = self.port_address.net();
+    buf[19] = self.port_address.sub_uni() >> 4;
+    // Status1: indicators normal, Port-Address programmed by network if it was
+    buf[23] = 0xc0 | if self.port_address != self.default_port_address { 0x20 } else { 0x10 };
+    if self.rdm.is_some() {
+      buf[23] |= 0x02;
+    }
+    write_name(&mut buf[26..26+SHORT_NAME_SIZE], &self.short_name);
+    write_name(&mut buf[44..44+LONG_NAME_SIZE], &self.long_name);
+    write_name(&mut buf[108..172], &report);
+    buf[172..174].copy_from_slice(&1_u16.to_be_bytes());
+    // DMX512 output port
+    buf[174] = 0x80;
+    // data transmitted, merging LTP/HTP is done by us
+    buf[182] = if open { 0x80 } else { 0 };
+    buf[190] = self.port_address.sub_uni() & 0xf;
+    // Style: StNode
+    buf[200] = 0;
+    // Status2: supports 15 bit Port-Address
+    buf[212] = 0x08;
+    buf
+  }
+  fn reply(&mut self, to: SocketAddr) {
+    let reply = self.poll_reply(to);
+    if let Err(e) = self.sock.send_to(&reply, SocketAddr::new(to.ip(), PORT)) {
+      println!("Error sending ArtPollReply to {}: {}", to, e);
+    }
+  }
+  // Remote configuration, and RDM changing the responders, from localhost and admins only.
+  fn is_admin(&self, from: SocketAddr) -> bool {
+    match &self.remote {
+      Some(admins) => from.ip().is_loopback() || admins.contains(&from.ip()),
+      None => false,
+    }
+  }
+  fn address(&mut self, from: SocketAddr, net: u8, sub: u8, uni: u8, short_name: String, long_name: String) {
+    if !self.is_admin(from) {
+      println!("ArtAddress from {} rejected", from);
+      return;
+    }
+    if !short_name.is_empty() {
+      self.short_name = short_name;
+    }
+    if !long_name.is_empty() {
+      self.long_name = long_name;
+    }
+    let port_address = self.port_address.program(net, sub, uni, self.default_port_address);
+    if port_address != self.port_address {
+      println!("Art-Net universe changed from {} to {} by {}", self.port_address, port_address, from);
+      self.port_address = port_address;
+    }
+    self.reply(from);
+  }
+  pub fn run(&mut self) {
+    let mut buf = [0u8; 1024];
+    loop {
+      self.ingest.flush();
+      let (size, addr) = match self.sock.recv_from(&mut buf) {
+        Ok(r) => r,
+        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock || e.kind() == std::io::ErrorKind::TimedOut => continue,
+        Err(e) => {
+          println!("Error receiving Art-Net: {}", e);
+          std::process::exit(1);
+        }
+      };
+      if self.auth.check_address(addr.ip()).is_err() {
+        self.stats.rejected.fetch_add(1, Ordering::Relaxed);
+        continue;
+      }
+      match parse(&buf[..size]) {
+        Some(Packet::Dmx { sequence, port_address, data }) if port_address == self.port_address => {
+          if !self.sequences.accept(addr, sequence, Instant::now()) {
+            continue;
+          }
+          self.stats.artnet.fetch_add(1, Ordering::Relaxed);
+          self.dmx_from = Some(addr.ip());
+          let values = data.iter().take(self.size).map(|v| Some(*v)).collect();
+          self.ingest.push(SourceId::ArtNet(addr), Command::Set { address: 0, values });
+        },
+        Some(Packet::Poll) => self.reply(addr),
+        Some(Packet::Sync) if self.dmx_from == Some(addr.ip()) => {
+          self.ingest.push(SourceId::ArtNet(addr), Command::Sync);
+        },
+        Some(Packet::Address { net, sub, uni, short_name, long_name }) => self.address(addr, net, sub, uni, short_name, long_name),
+        Some(Packet::TodRequest { net, addresses }) => {
+          if addresses.iter().any(|a| self.is_ours(net, *a)) {
+            self.rdm(addr, RdmCommand::Tod { discover: false });
+          }
+        },
+        Some(Packet::TodControl { net, flush, address }) if self.is_ours(net, address) => {
+          if flush && !self.is_admin(addr) {
+            println!("ArtTodControl flush from {} rejected", addr);
+            continue;
+          }
+          self.rdm(addr, RdmCommand::Tod { discover: flush });
+        },
+        Some(Packet::Rdm { net, address, packet }) if self.is_ours(net, address) => {
+          if !rdm::is_get(packet) && !self.is_admin(addr) {
+            println!("ArtRdm SET from {} rejected", addr);
+            continue;
+          }
+          self.rdm(addr, RdmCommand::Request(packet.to_vec()));
+        },
+        _ => continue,
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn port_address() {
+    let a = PortAddress::parse("1:2:3").unwrap();
+    assert_eq!((a.net(), a.sub_uni()), (1, 0x23));
+    assert_eq!(PortAddress::parse("291"), Some(a));
+    assert_eq!(a.to_string(), "1:2:3");
+    assert_eq!(PortAddress::parse("128:0:0"), None);
+    assert_eq!(PortAddress::parse("0:16:0"), None);
+    assert_eq!(PortAddress::parse("32768"), None);
+    assert_eq!(PortAddress::parse("1:2"), None);
+  }
+
+  #[test]
+  fn dmx_round_trip() {
+    let a = PortAddress::parse("1:2:3").unwrap();
+    let data: Vec<u8> = (0..=254).collect();
+    let buf = dmx_packet(a, 7, &data);
+    // padded to even length
+    assert_eq!(buf.len(), 18 + 256);
+    match parse(&buf) {
+      Some(Packet::Dmx { sequence, port_address, data: d }) => {
+        assert_eq!((sequence, port_address), (7, a));
+        assert_eq!(&d[..255], &data[..]);
+        assert_eq!(d[255], 0);
+      },
+      p => panic!("unexpected {:?}", p),
+    }
+  }
+
+  #[test]
+  fn malformed_dmx() {
+    let a = PortAddress::parse("0:0:1").unwrap();
+    let buf = dmx_packet(a, 1, &[1, 2, 3, 4]);
+    assert!(parse(&buf).is_some());
+    // truncated data, header only, other protocol
+    assert!(parse(&buf[..buf.len() - 1]).is_none());
+    assert!(parse(&buf[..17]).is_none());
+    assert!(parse(&buf[..11]).is_none());
+    let mut other = buf.clone();
+    other[0] = b'X';
+    assert!(parse(&other).is_none());
+    // protocol version too old
+    let mut old = buf.clone();
+    old[11] = 13;
+    assert!(parse(&old).is_none());
+    // length out of range
+    let mut long = buf.clone();
+    long[16..18].copy_from_slice(&514_u16.to_be_bytes());
+    long.resize(18 + 514, 0);
+    assert!(parse(&long).is_none());
+    let mut short = buf.clone();
+    short[16..18].copy_from_slice(&1_u16.to_be_bytes());
+    assert!(parse(&short).is_none());
+  }
+
+  #[test]
+  fn poll_and_sync() {
+    assert!(matches!(parse(&header(OP_POLL)), Some(Packet::Poll)));
+    assert!(matches!(parse(&header(OP_SYNC)), Some(Packet::Sync)));
+    assert!(parse(&header(0x1234)).is_none());
+  }
+
+  #[test]
+  fn rdm_packets() {
+    let a = PortAddress::parse("1:2:3").unwrap();
+    let request = rdm::encode(rdm::Uid([1, 2, 3, 4, 5, 6]), rdm::Uid([7, 8, 9, 10, 11, 12]), 1, 0x20, 0x0060, &[]);
+    let buf = rdm_packet(a, &request[1..]);
+    match parse(&buf) {
+      Some(Packet::Rdm { net, address, packet }) => {
+        assert_eq!((net, address), (1, 0x23));
+        assert_eq!(packet, &request[1..]);
+      },
+      p => panic!("unexpected {:?}", p),
+    }
+    // header only
+    assert!(parse(&buf[..24]).is_none());
+    let mut other = buf.clone();
+    other[22] = 1;
+    assert!(parse(&other).is_none());
+  }
+
+  #[test]
+  fn tod_packets() {
+    let mut buf = header(OP_TOD_REQUEST);
+    buf.extend_from_slice(&[0; 9]);
+    buf.extend_from_slice(&[0x81, 0, 2, 0x23, 0x24]);
+    match parse(&buf) {
+      Some(Packet::TodRequest { net, addresses }) => assert_eq!((net, addresses), (1, vec![0x23, 0x24])),
+      p => panic!("unexpected {:?}", p),
+    }
+    // fewer addresses than counted
+    assert!(parse(&buf[..buf.len() - 1]).is_none());
+    let mut buf = header(OP_TOD_CONTROL);
+    buf.extend_from_slice(&[0; 9]);
+    buf.extend_from_slice(&[1, ATC_FLUSH, 0x23]);
+    assert!(matches!(parse(&buf), Some(Packet::TodControl { net: 1, flush: true, address: 0x23 })));
+    assert!(parse(&buf[..23]).is_none());
+    // blocks of 200 UIDs
+    let tod: Vec<rdm::Uid> = (0..450_u32).map(|i| rdm::Uid([0x7f, 0xf0, 0, 0, (i >> 8) as u8, i as u8])).collect();
+    let blocks = tod_data(PortAddress::parse("1:2:3").unwrap(), &tod);
+    assert_eq!(blocks.len(), 3);
+    assert_eq!(blocks[2][27], 50);
+    assert_eq!(blocks[2].len(), 28 + 50 * 6);
+    assert_eq!(tod_data(PortAddress::parse("0:0:0").unwrap(), &[]).len(), 1);
+  }
+
+  #[test]
+  fn sequences() {
+    let mut sequences = Sequences::default();
+    let addr: SocketAddr = "10.0.0.1:6454".parse().unwrap();
+    let now = Instant::now();
+    assert!(sequences.accept(addr, 10, now));
+    assert!(!sequences.accept(addr, 9, now));
+    assert!(!sequences.accept(addr, 10, now));
+    assert!(sequences.accept(addr, 11, now));
+    // zero is always accepted, wrapping around is not out of order
+    assert!(sequences.accept(addr, 0, now));
+    assert!(sequences.accept(addr, 100, now));
+    assert!(sequences.accept(addr, 200, now));
+    assert!(sequences.accept(addr, 1, now));
+    // a restarted sender is accepted after a while
+    assert!(sequences.accept(addr, 1, now + SEQUENCE_RESET));
+  }
+}
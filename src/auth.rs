@@ -0,0 +1,191 @@
+use std::{collections::HashMap, fmt, net::IpAddr, time::{SystemTime, UNIX_EPOCH}};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::hex;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Cidr {
+  addr: IpAddr,
+  prefix: u8,
+}
+impl Cidr {
+  // "10.0.0.0/8", "fd00::/8" or single address
+  pub fn parse(s: &str) -> Option<Cidr> {
+    let (addr, prefix) = match s.split_once('/') {
+      Some((a, p)) => (a.parse::<IpAddr>().ok()?, Some(p.parse::<u8>().ok()?)),
+      None => (s.parse::<IpAddr>().ok()?, None),
+    };
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    let prefix = prefix.unwrap_or(max);
+    if prefix > max {
+      return None;
+    }
+    Some(Cidr { addr, prefix })
+  }
+  pub fn contains(&self, ip: IpAddr) -> bool {
+    let ip = match ip {
+      IpAddr::V6(v6) if self.addr.is_ipv4() => match v6.to_ipv4_mapped() {
+        Some(v4) => IpAddr::V4(v4),
+        None => return false,
+      },
+      ip => ip,
+    };
+    match (self.addr, ip) {
+      (IpAddr::V4(net), IpAddr::V4(ip)) => {
+        let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+        u32::from(net) & mask == u32::from(ip) & mask
+      },
+      (IpAddr::V6(net), IpAddr::V6(ip)) => {
+        let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+        u128::from(net) & mask == u128::from(ip) & mask
+      },
+      _ => false,
+    }
+  }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AuthError {
+  AddressNotAllowed,
+  MissingSignature,
+  BadSignature,
+  MissingTimestamp,
+  Replayed,
+}
+impl fmt::Display for AuthError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      AuthError::AddressNotAllowed => write!(f, "address not allowed"),
+      AuthError::MissingSignature => write!(f, "missing signature"),
+      AuthError::BadSignature => write!(f, "bad signature"),
+      AuthError::MissingTimestamp => write!(f, "missing timestamp"),
+      AuthError::Replayed => write!(f, "stale or repeated timestamp"),
+    }
+  }
+}
+impl std::error::Error for AuthError {}
+
+// signed messages are accepted that far from the local clock
+pub const REPLAY_WINDOW_MS: i64 = 10_000;
+
+fn now_ms() -> i64 {
+  SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as i64)
+}
+
+// Sender allowlist and optional HMAC-SHA256 signed mode.
+// Signed messages carry the signature as the last argument, either 32 byte blob or hex string,
+// calculated over the encoded message without that argument. The argument before it is the
+// sender's clock as int64 milliseconds since the Unix epoch, increasing with every message.
+pub struct Auth {
+  allow: Vec<Cidr>,
+  secret: Option<Vec<u8>>,
+  // last accepted timestamp per sender
+  last: HashMap<IpAddr, i64>,
+}
+
+impl Auth {
+  pub fn new(allow: Vec<Cidr>, secret: Option<Vec<u8>>) -> Auth {
+    Auth { allow, secret, last: HashMap::new() }
+  }
+  // Neither allowlist nor secret, every sender is trusted.
+  pub fn is_open(&self) -> bool {
+    self.allow.is_empty() && self.secret.is_none()
+  }
+  // Empty allowlist lets everyone in.
+  pub fn check_address(&self, ip: IpAddr) -> Result<(), AuthError> {
+    if self.allow.is_empty() || self.allow.iter().any(|c| c.contains(ip)) {
+      Ok(())
+    } else {
+      Err(AuthError::AddressNotAllowed)
+    }
+  }
+  // Returns the message without signature and timestamp.
+  pub fn verify(&mut self, ip: IpAddr, msg: rosc::OscMessage) -> Result<rosc::OscMessage, AuthError> {
+    self.verify_at(ip, msg, now_ms())
+  }
+  fn verify_at(&mut self, ip: IpAddr, mut msg: rosc::OscMessage, now: i64) -> Result<rosc::OscMessage, AuthError> {
+    let secret = match &self.secret {
+      Some(s) => s,
+      None => return Ok(msg),
+    };
+    let signature = match msg.args.pop() {
+      Some(rosc::OscType::Blob(b)) => b,
+      Some(rosc::OscType::String(s)) => hex::decode(&s).ok_or(AuthError::BadSignature)?,
+      _ => return Err(AuthError::MissingSignature),
+    };
+    let signed = rosc::encoder::encode(&rosc::OscPacket::Message(msg.clone()))
+      .map_err(|_| AuthError::BadSignature)?;
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).map_err(|_| AuthError::BadSignature)?;
+    mac.update(&signed);
+    mac.verify_slice(&signature).map_err(|_| AuthError::BadSignature)?;
+    let timestamp = match msg.args.pop() {
+      Some(rosc::OscType::Long(t)) => t,
+      _ => return Err(AuthError::MissingTimestamp),
+    };
+    if (timestamp - now).abs() > REPLAY_WINDOW_MS || self.last.get(&ip).is_some_and(|l| timestamp <= *l) {
+      return Err(AuthError::Replayed);
+    }
+    // older timestamps are out of the window anyway
+    self.last.retain(|_, l| now - *l <= REPLAY_WINDOW_MS);
+    self.last.insert(ip, timestamp);
+    Ok(msg)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const NOW: i64 = 1_700_000_000_000;
+
+  fn signed(secret: &[u8], timestamp: i64) -> rosc::OscMessage {
+    let mut msg = rosc::OscMessage {
+      addr: "/1/dmx/0".to_string(),
+      args: vec![rosc::OscType::Int(255), rosc::OscType::Long(timestamp)],
+    };
+    let encoded = rosc::encoder::encode(&rosc::OscPacket::Message(msg.clone())).unwrap();
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).unwrap();
+    mac.update(&encoded);
+    msg.args.push(rosc::OscType::Blob(mac.finalize().into_bytes().to_vec()));
+    msg
+  }
+
+  #[test]
+  fn verify_strips_signature_and_timestamp() {
+    let mut auth = Auth::new(Vec::new(), Some(b"secret".to_vec()));
+    let msg = auth.verify_at("10.0.0.1".parse().unwrap(), signed(b"secret", NOW), NOW).unwrap();
+    assert_eq!(msg.args, vec![rosc::OscType::Int(255)]);
+  }
+
+  #[test]
+  fn verify_rejects_replays() {
+    let mut auth = Auth::new(Vec::new(), Some(b"secret".to_vec()));
+    let ip = "10.0.0.1".parse().unwrap();
+    assert!(auth.verify_at(ip, signed(b"secret", NOW), NOW).is_ok());
+    assert_eq!(auth.verify_at(ip, signed(b"secret", NOW), NOW), Err(AuthError::Replayed));
+    assert_eq!(auth.verify_at(ip, signed(b"secret", NOW - 1), NOW), Err(AuthError::Replayed));
+    assert!(auth.verify_at(ip, signed(b"secret", NOW + 1), NOW).is_ok());
+    // other senders keep their own sequence
+    assert!(auth.verify_at("10.0.0.2".parse().unwrap(), signed(b"secret", NOW), NOW).is_ok());
+  }
+
+  #[test]
+  fn verify_rejects_stale_timestamps() {
+    let mut auth = Auth::new(Vec::new(), Some(b"secret".to_vec()));
+    let ip = "10.0.0.1".parse().unwrap();
+    assert_eq!(auth.verify_at(ip, signed(b"secret", NOW - REPLAY_WINDOW_MS - 1), NOW), Err(AuthError::Replayed));
+    assert_eq!(auth.verify_at(ip, signed(b"secret", NOW + REPLAY_WINDOW_MS + 1), NOW), Err(AuthError::Replayed));
+  }
+
+  #[test]
+  fn verify_rejects_bad_signatures() {
+    let mut auth = Auth::new(Vec::new(), Some(b"secret".to_vec()));
+    let ip = "10.0.0.1".parse().unwrap();
+    assert_eq!(auth.verify_at(ip, signed(b"other", NOW), NOW), Err(AuthError::BadSignature));
+    let mut msg = signed(b"secret", NOW);
+    msg.args.pop();
+    assert_eq!(auth.verify_at(ip, msg, NOW), Err(AuthError::MissingSignature));
+  }
+}
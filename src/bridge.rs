@@ -0,0 +1,113 @@
+use std::{net::{SocketAddr, ToSocketAddrs, UdpSocket}, time::{Duration, Instant}};
+
+use crate::{artnet::{self, PortAddress}, sacn, led, source::DEFAULT_PRIORITY};
+
+// unchanged frames are sent again after that long, so receivers don't time out
+const KEEPALIVE: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Protocol {
+  ArtNet(PortAddress),
+  Sacn(u16),
+  // first pixel of the strip the frame goes to
+  Ddp(u16),
+  Wled(u16),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Target {
+  pub protocol: Protocol,
+  pub addr: SocketAddr,
+  sequence: u8,
+}
+
+fn resolve(host: &str, port: u16) -> Option<SocketAddr> {
+  let host = if host.contains(':') { host.to_string() } else { format!("{}:{}", host, port) };
+  host.to_socket_addrs().ok()?.next()
+}
+
+// "[<start pixel>@]<host>[:<port>]"
+fn parse_pixels(spec: &str, port: u16) -> Option<(u16, SocketAddr)> {
+  match spec.split_once('@') {
+    Some((start, host)) => Some((start.parse::<u16>().ok()?, resolve(host, port)?)),
+    None => Some((0, resolve(spec, port)?)),
+  }
+}
+
+impl Target {
+  // "artnet:<Port-Address>@<host>[:<port>]" or "sacn:<universe>[@<host>[:<port>]]",
+  // sACN goes to the universe's multicast group without host;
+  // "ddp:[<start pixel>@]<host>[:<port>]" or "wled:[<start pixel>@]<host>[:<port>]" for pixel controllers
+  pub fn parse(spec: &str) -> Option<Target> {
+    if let Some(rest) = spec.strip_prefix("artnet:") {
+      let (port_address, host) = rest.split_once('@')?;
+      let port_address = PortAddress::parse(port_address)?;
+      Some(Target { protocol: Protocol::ArtNet(port_address), addr: resolve(host, artnet::PORT)?, sequence: 0 })
+    } else if let Some(rest) = spec.strip_prefix("sacn:") {
+      let (universe, host) = match rest.split_once('@') {
+        Some((u, h)) => (u, Some(h)),
+        None => (rest, None),
+      };
+      let universe = universe.parse::<u16>().ok().filter(|u| *u >= 1 && *u <= sacn::MAX_UNIVERSE)?;
+      let addr = match host {
+        Some(h) => resolve(h, sacn::PORT)?,
+        None => SocketAddr::new(sacn::multicast_group(universe).into(), sacn::PORT),
+      };
+      Some(Target { protocol: Protocol::Sacn(universe), addr, sequence: 0 })
+    } else if let Some(rest) = spec.strip_prefix("ddp:") {
+      let (start, addr) = parse_pixels(rest, led::DDP_PORT)?;
+      Some(Target { protocol: Protocol::Ddp(start), addr, sequence: 0 })
+    } else if let Some(rest) = spec.strip_prefix("wled:") {
+      let (start, addr) = parse_pixels(rest, led::WLED_PORT)?;
+      Some(Target { protocol: Protocol::Wled(start), addr, sequence: 0 })
+    } else {
+      None
+    }
+  }
+}
+
+// Sends the output frame as Art-Net or sACN universes, or to pixel controllers, at most `rate` times per second.
+pub struct Bridge {
+  sock: UdpSocket,
+  targets: Vec<Target>,
+  cid: [u8; 16],
+  interval: Duration,
+  last: Vec<u8>,
+  last_sent: Option<Instant>,
+}
+
+impl Bridge {
+  pub fn new(sock: UdpSocket, targets: Vec<Target>, cid: [u8; 16], rate: f64) -> Bridge {
+    Bridge { sock, targets, cid, interval: Duration::from_secs_f64(1_f64 / rate), last: Vec::new(), last_sent: None }
+  }
+  pub fn send(&mut self, now: Instant, frame: &[u8]) {
+    if let Some(sent) = self.last_sent {
+      let since = now.saturating_duration_since(sent);
+      if since < self.interval || (frame == self.last.as_slice() && since < KEEPALIVE) {
+        return;
+      }
+    }
+    self.last_sent = Some(now);
+    self.last.clear();
+    self.last.extend_from_slice(frame);
+    for target in self.targets.iter_mut() {
+      target.sequence = target.sequence.wrapping_add(1);
+      let packet = match target.protocol {
+        Protocol::ArtNet(port_address) => {
+          // 0 would switch sequence checking off
+          if target.sequence == 0 {
+            target.sequence = 1;
+          }
+          artnet::dmx_packet(port_address, target.sequence, frame)
+        },
+        Protocol::Sacn(universe) => sacn::data_packet(&self.cid, "roscdmx", DEFAULT_PRIORITY, universe, target.sequence, frame),
+        // DDP sequence is 1-15
+        Protocol::Ddp(start) => led::ddp_packet(target.sequence % 15 + 1, start as u32 * 3, frame),
+        Protocol::Wled(start) => led::wled_packet(start, frame),
+      };
+      if let Err(e) = self.sock.send_to(&packet, target.addr) {
+        println!("Error sending output to {}: {}", target.addr, e);
+      }
+    }
+  }
+}
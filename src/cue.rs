@@ -0,0 +1,211 @@
+use std::{fmt, error::Error, time::{Duration, Instant}};
+
+#[derive(Debug)]
+pub enum ShowError {
+  IOError(std::io::Error),
+  Syntax(usize, String),
+}
+impl fmt::Display for ShowError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ShowError::IOError(e) => write!(f, "Can't read show file: {}", e),
+      ShowError::Syntax(line, e) => write!(f, "Show file line {}: {}", line, e),
+    }
+  }
+}
+impl Error for ShowError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      ShowError::IOError(e) => Some(e),
+      ShowError::Syntax(_, _) => None,
+    }
+  }
+}
+impl From<std::io::Error> for ShowError {
+  fn from(e: std::io::Error) -> Self {
+    ShowError::IOError(e)
+  }
+}
+
+#[derive(Debug)]
+pub struct Cue {
+  pub number: f64,
+  pub scene: String,
+  // used for channels going up
+  pub fade_in: Duration,
+  // used for channels going down
+  pub fade_out: Duration,
+  pub delay: Duration,
+  // if set, next cue is started this long after the fades of this one are complete
+  pub follow: Option<Duration>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Phase {
+  Stopped,
+  Delay(Instant),
+  Fading(Instant),
+  Follow(Instant),
+  Done,
+}
+
+#[derive(Debug)]
+pub struct CueList {
+  cues: Vec<Cue>,
+  current: Option<usize>,
+  phase: Phase,
+}
+
+fn parse_seconds(line: usize, key: &str, value: &str) -> Result<Duration, ShowError> {
+  match value.parse::<f64>().ok().and_then(|s| Duration::try_from_secs_f64(s).ok()) {
+    Some(d) => Ok(d),
+    None => Err(ShowError::Syntax(line, format!("bad {} time {:?}", key, value))),
+  }
+}
+
+// Cue numbers are compared to three decimals, OSC floats don't hold more.
+fn cue_key(number: f64) -> i64 {
+  (number * 1000_f64).round() as i64
+}
+
+impl CueList {
+  pub fn new() -> CueList {
+    CueList { cues: Vec::new(), current: None, phase: Phase::Stopped }
+  }
+  pub fn load(path: &str) -> Result<CueList, ShowError> {
+    CueList::parse(&std::fs::read_to_string(path)?)
+  }
+  // "cue <number> <scene> [in=<s>] [out=<s>] [time=<s>] [delay=<s>] [follow=<s>]"
+  pub fn parse(text: &str) -> Result<CueList, ShowError> {
+    let mut list = CueList::new();
+    for (i, line) in text.lines().enumerate() {
+      let n = i + 1;
+      let line = line.split('#').next().unwrap_or("");
+      let mut words = line.split_whitespace();
+      match words.next() {
+        None => continue,
+        Some("cue") => {},
+        Some(w) => return Err(ShowError::Syntax(n, format!("unknown directive {:?}", w))),
+      }
+      let number = match words.next().map(|w| w.parse::<f64>()) {
+        Some(Ok(number)) if number.is_finite() => number,
+        _ => return Err(ShowError::Syntax(n, "cue number expected".to_string())),
+      };
+      if list.cues.last().is_some_and(|c| cue_key(c.number) >= cue_key(number)) {
+        return Err(ShowError::Syntax(n, format!("cue {} is out of order", number)));
+      }
+      let scene = match words.next() {
+        Some(s) => s.to_string(),
+        None => return Err(ShowError::Syntax(n, "scene name expected".to_string())),
+      };
+      let mut cue = Cue {
+        number, scene,
+        fade_in: Duration::ZERO,
+        fade_out: Duration::ZERO,
+        delay: Duration::ZERO,
+        follow: None,
+      };
+      for w in words {
+        match w.split_once('=') {
+          Some(("in", v)) => cue.fade_in = parse_seconds(n, "in", v)?,
+          Some(("out", v)) => cue.fade_out = parse_seconds(n, "out", v)?,
+          Some(("time", v)) => {
+            cue.fade_in = parse_seconds(n, "fade", v)?;
+            cue.fade_out = cue.fade_in;
+          },
+          Some(("delay", v)) => cue.delay = parse_seconds(n, "delay", v)?,
+          Some(("follow", v)) => cue.follow = Some(parse_seconds(n, "follow", v)?),
+          _ => return Err(ShowError::Syntax(n, format!("unexpected {:?}", w))),
+        }
+      }
+      list.cues.push(cue);
+    }
+    Ok(list)
+  }
+  fn start(&mut self, index: usize) {
+    self.current = Some(index);
+    self.phase = Phase::Delay(Instant::now() + self.cues[index].delay);
+  }
+  pub fn go(&mut self) {
+    let next = self.current.map_or(0, |c| c + 1);
+    if next < self.cues.len() {
+      self.start(next);
+    }
+  }
+  pub fn back(&mut self) {
+    if let Some(c) = self.current {
+      if c > 0 {
+        self.start(c - 1);
+      }
+    }
+  }
+  pub fn goto(&mut self, number: f64) -> bool {
+    match self.cues.iter().position(|c| cue_key(c.number) == cue_key(number)) {
+      Some(i) => {
+        self.start(i);
+        true
+      },
+      None => false,
+    }
+  }
+  pub fn stop(&mut self) {
+    self.phase = Phase::Stopped;
+  }
+  pub fn current(&self) -> Option<&Cue> {
+    self.current.map(|c| &self.cues[c])
+  }
+  pub fn state(&self) -> &'static str {
+    match self.phase {
+      Phase::Stopped => "stopped",
+      Phase::Delay(_) => "delay",
+      Phase::Fading(_) => "fading",
+      Phase::Follow(_) => "follow",
+      Phase::Done => "done",
+    }
+  }
+  // Advances the playback, returns cue which fades have to be started now.
+  pub fn tick(&mut self, now: Instant) -> Option<&Cue> {
+    let current = self.current?;
+    match self.phase {
+      Phase::Delay(at) if now >= at => {
+        let cue = &self.cues[current];
+        self.phase = Phase::Fading(at + cue.fade_in.max(cue.fade_out));
+        return Some(cue);
+      },
+      Phase::Fading(at) if now >= at => {
+        self.phase = match self.cues[current].follow {
+          Some(follow) => Phase::Follow(at + follow),
+          None => Phase::Done,
+        };
+      },
+      Phase::Follow(at) if now >= at => {
+        self.phase = Phase::Done;
+        self.go();
+      },
+      _ => {},
+    }
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn goto_matches_single_precision_numbers() {
+    let mut list = CueList::parse("cue 1 a\ncue 1.1 b\ncue 2.25 c\n").unwrap();
+    assert!(list.goto(1.1_f32 as f64));
+    assert_eq!(list.current().unwrap().scene, "b");
+    assert!(list.goto(2.25));
+    assert!(!list.goto(1.2));
+  }
+
+  #[test]
+  fn malformed_show() {
+    assert!(CueList::parse("cue 1 a\ncue 1.0001 b\n").is_err());
+    assert!(CueList::parse("cue 1 a time=1e30\n").is_err());
+    assert!(CueList::parse("cue 1 a time=-1\n").is_err());
+    assert!(CueList::parse("cue x a\n").is_err());
+  }
+}
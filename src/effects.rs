@@ -0,0 +1,134 @@
+use std::{collections::BTreeMap, time::Instant, f32::consts::PI};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Waveform {
+  Sine,
+  Saw,
+  Square,
+  Random,
+}
+impl Waveform {
+  pub fn from_name(name: &str) -> Option<Waveform> {
+    match name {
+      "sine" => Some(Waveform::Sine),
+      "saw" => Some(Waveform::Saw),
+      "square" => Some(Waveform::Square),
+      "random" => Some(Waveform::Random),
+      _ => None,
+    }
+  }
+  // position is in cycles, result is in 0..1
+  fn sample(&self, position: f32, seed: u64) -> f32 {
+    let phase = position.rem_euclid(1_f32);
+    match self {
+      Waveform::Sine => 0.5_f32 - 0.5_f32 * (2_f32 * PI * phase).cos(),
+      Waveform::Saw => phase,
+      Waveform::Square => if phase < 0.5_f32 { 1_f32 } else { 0_f32 },
+      Waveform::Random => {
+        let h = splitmix64(seed ^ (position.floor() as i64 as u64));
+        (h >> 40) as f32 / (1u64 << 24) as f32
+      },
+    }
+  }
+}
+
+fn splitmix64(x: u64) -> u64 {
+  let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+  z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+  z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+  z ^ (z >> 31)
+}
+
+// How effect output is composited with the base value of the channel
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Blend {
+  Htp,
+  Add,
+  Scale,
+}
+impl Blend {
+  pub fn from_name(name: &str) -> Option<Blend> {
+    match name {
+      "htp" => Some(Blend::Htp),
+      "add" => Some(Blend::Add),
+      "scale" => Some(Blend::Scale),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Debug)]
+pub struct Effect {
+  pub waveform: Waveform,
+  pub blend: Blend,
+  // cycles per second
+  pub rate: f32,
+  // phase offset between first and last channel, in cycles
+  pub spread: f32,
+  // may be negative for additive effects
+  pub min: i32,
+  pub max: i32,
+  pub channels: Vec<usize>,
+  start: Instant,
+  running: bool,
+}
+impl Effect {
+  pub fn new(waveform: Waveform, blend: Blend, rate: f32, spread: f32, min: i32, max: i32, channels: Vec<usize>) -> Effect {
+    Effect {
+      waveform, blend, rate, spread, min, max, channels,
+      start: Instant::now(),
+      running: true,
+    }
+  }
+  fn apply(&self, frame: &mut [u8], now: Instant) {
+    let t = now.saturating_duration_since(self.start).as_secs_f32() * self.rate;
+    let n = self.channels.len() as f32;
+    for (i, &c) in self.channels.iter().enumerate() {
+      if c >= frame.len() {
+        continue;
+      }
+      let w = self.waveform.sample(t - self.spread * i as f32 / n, c as u64);
+      let v = self.min as f32 + (self.max - self.min) as f32 * w;
+      let base = frame[c] as f32;
+      let out = match self.blend {
+        Blend::Htp => base.max(v),
+        Blend::Add => base + v,
+        Blend::Scale => base * v / 255_f32,
+      };
+      frame[c] = out.round().clamp(0_f32, 255_f32) as u8;
+    }
+  }
+}
+
+#[derive(Debug, Default)]
+pub struct Effects {
+  effects: BTreeMap<String, Effect>,
+}
+
+impl Effects {
+  pub fn new() -> Effects {
+    Effects::default()
+  }
+  pub fn define(&mut self, name: String, effect: Effect) {
+    self.effects.insert(name, effect);
+  }
+  pub fn start(&mut self, name: &str) {
+    if let Some(e) = self.effects.get_mut(name) {
+      e.start = Instant::now();
+      e.running = true;
+    }
+  }
+  pub fn stop(&mut self, name: &str) {
+    if let Some(e) = self.effects.get_mut(name) {
+      e.running = false;
+    }
+  }
+  pub fn remove(&mut self, name: &str) {
+    self.effects.remove(name);
+  }
+  pub fn apply(&self, frame: &mut [u8], now: Instant) {
+    for e in self.effects.values().filter(|e| e.running) {
+      e.apply(frame, now);
+    }
+  }
+}
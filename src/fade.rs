@@ -0,0 +1,118 @@
+use std::time::{Duration, Instant};
+
+use crate::patch::Attribute16;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Profile {
+  Linear,
+  SCurve,
+}
+impl Profile {
+  pub fn from_name(name: &str) -> Option<Profile> {
+    match name {
+      "linear" => Some(Profile::Linear),
+      "s" | "scurve" | "s-curve" => Some(Profile::SCurve),
+      _ => None,
+    }
+  }
+  fn shape(&self, t: f32) -> f32 {
+    match self {
+      Profile::Linear => t,
+      Profile::SCurve => t * t * (3_f32 - 2_f32 * t),
+    }
+  }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Target {
+  Channel(usize),
+  Attribute16(Attribute16),
+}
+impl Target {
+  pub fn channels(&self) -> Vec<usize> {
+    match self {
+      Target::Channel(c) => vec![*c],
+      Target::Attribute16(a) => vec![a.coarse, a.fine],
+    }
+  }
+  fn touches(&self, channel: usize) -> bool {
+    match self {
+      Target::Channel(c) => *c == channel,
+      Target::Attribute16(a) => a.coarse == channel || a.fine == channel,
+    }
+  }
+  fn overlaps(&self, other: &Target) -> bool {
+    match other {
+      Target::Channel(c) => self.touches(*c),
+      Target::Attribute16(a) => self.touches(a.coarse) || self.touches(a.fine),
+    }
+  }
+  fn read(&self, data: &[u8]) -> f32 {
+    match self {
+      Target::Channel(c) => data.get(*c).copied().unwrap_or(0) as f32,
+      Target::Attribute16(a) => {
+        let coarse = data.get(a.coarse).copied().unwrap_or(0) as u16;
+        let fine = data.get(a.fine).copied().unwrap_or(0) as u16;
+        (coarse << 8 | fine) as f32
+      },
+    }
+  }
+  fn write(&self, data: &mut [u8], value: f32) {
+    match self {
+      Target::Channel(c) => if *c < data.len() {
+        data[*c] = value.round().clamp(0_f32, 255_f32) as u8;
+      },
+      Target::Attribute16(a) => a.write(data, value.round().clamp(0_f32, 65535_f32) as u16),
+    }
+  }
+}
+
+#[derive(Debug)]
+struct Fade {
+  target: Target,
+  from: f32,
+  to: f32,
+  start: Instant,
+  duration: Duration,
+  profile: Profile,
+}
+
+#[derive(Debug, Default)]
+pub struct Fades {
+  fades: Vec<Fade>,
+}
+
+impl Fades {
+  pub fn new() -> Fades {
+    Fades::default()
+  }
+  // Starts fade from the current value, replacing any running fade on the same channels.
+  pub fn start(&mut self, data: &[u8], target: Target, to: f32, duration: Duration, profile: Profile) {
+    self.fades.retain(|f| !f.target.overlaps(&target));
+    self.fades.push(Fade {
+      target,
+      from: target.read(data),
+      to,
+      start: Instant::now(),
+      duration,
+      profile,
+    });
+  }
+  pub fn cancel(&mut self, channel: usize) {
+    if !self.fades.is_empty() {
+      self.fades.retain(|f| !f.target.touches(channel));
+    }
+  }
+  pub fn update(&mut self, data: &mut [u8], now: Instant) {
+    self.fades.retain(|f| {
+      let elapsed = now.saturating_duration_since(f.start);
+      if elapsed >= f.duration {
+        f.target.write(data, f.to);
+        return false;
+      }
+      let t = f.profile.shape(elapsed.as_secs_f32() / f.duration.as_secs_f32());
+      f.target.write(data, f.from + (f.to - f.from) * t);
+      true
+    });
+  }
+}
@@ -0,0 +1,94 @@
+use std::{collections::BTreeMap, net::SocketAddr, time::{Duration, Instant}};
+
+const MAX_SUBSCRIBERS: usize = 32;
+// subscribers have to renew by sending "/subscribe" again within that time
+pub const SUBSCRIPTION_TTL: Duration = Duration::from_secs(60);
+// keeps bundles within a single UDP datagram
+const BUNDLE_SIZE: usize = 48;
+
+// Sends changed channel values to subscribed clients, as "/<universe>/dmx/<address> <value>"
+// messages batched into bundles, at most once per interval.
+pub struct Feedback {
+  prefix: String,
+  interval: Duration,
+  next: Instant,
+  // last values sent to the target, None if it needs full state
+  targets: BTreeMap<SocketAddr, Option<Vec<u8>>>,
+  // when the subscriptions run out, fixed targets never do
+  expires: BTreeMap<SocketAddr, Instant>,
+  // configured targets, never unsubscribed
+  fixed: Vec<SocketAddr>,
+}
+
+impl Feedback {
+  // prefix is "/<universe_number>/"
+  pub fn new(prefix: &str, rate: f64, fixed: Vec<SocketAddr>) -> Feedback {
+    Feedback {
+      prefix: prefix.to_string(),
+      interval: Duration::from_secs_f64(1_f64 / rate),
+      next: Instant::now(),
+      targets: fixed.iter().map(|t| (*t, None)).collect(),
+      expires: BTreeMap::new(),
+      fixed,
+    }
+  }
+  pub fn subscribe(&mut self, target: SocketAddr) -> bool {
+    if !self.targets.contains_key(&target) && self.targets.len() >= MAX_SUBSCRIBERS {
+      return false;
+    }
+    // renewal keeps the values already sent
+    self.targets.entry(target).or_insert(None);
+    if !self.fixed.contains(&target) {
+      self.expires.insert(target, Instant::now() + SUBSCRIPTION_TTL);
+    }
+    true
+  }
+  pub fn unsubscribe(&mut self, target: SocketAddr) {
+    if !self.fixed.contains(&target) {
+      self.targets.remove(&target);
+      self.expires.remove(&target);
+    }
+  }
+  fn bundle(&self, messages: Vec<rosc::OscPacket>) -> rosc::OscPacket {
+    rosc::OscPacket::Bundle(rosc::OscBundle {
+      timetag: (0, 1).into(), // immediately
+      content: messages,
+    })
+  }
+  pub fn poll(&mut self, data: &[u8], now: Instant) -> Vec<(SocketAddr, rosc::OscPacket)> {
+    let mut packets = Vec::new();
+    let expired: Vec<SocketAddr> = self.expires.iter().filter(|(_, e)| now >= **e).map(|(t, _)| *t).collect();
+    for target in expired {
+      println!("Feedback subscription of {} expired", target);
+      self.unsubscribe(target);
+    }
+    if self.targets.is_empty() || now < self.next {
+      return packets;
+    }
+    self.next = now + self.interval;
+    let mut updates = Vec::new();
+    for (target, sent) in self.targets.iter_mut() {
+      let mut messages = Vec::new();
+      for (c, v) in data.iter().enumerate() {
+        if sent.as_ref().is_none_or(|s| s[c] != *v) {
+          messages.push(rosc::OscPacket::Message(rosc::OscMessage {
+            addr: format!("{}dmx/{}", self.prefix, c),
+            args: vec![rosc::OscType::Int(*v as i32)],
+          }));
+        }
+      }
+      if !messages.is_empty() {
+        *sent = Some(data.to_vec());
+        updates.push((*target, messages));
+      }
+    }
+    for (target, mut messages) in updates {
+      while !messages.is_empty() {
+        let rest = messages.split_off(messages.len().min(BUNDLE_SIZE));
+        packets.push((target, self.bundle(messages)));
+        messages = rest;
+      }
+    }
+    packets
+  }
+}
@@ -143,6 +143,11 @@ pub struct DeviceListInfoNode {
   pub description: String,
 }
 
+#[derive(Debug)]
+pub struct DeviceInfo {
+  pub serial_number: String,
+}
+
 const FT_OPEN_BY_SERIAL_NUMBER:u32 =	1;
 const FT_OPEN_BY_DESCRIPTION:u32 =		2;
 const FT_OPEN_BY_LOCATION:u32 =			  4;
@@ -195,6 +200,15 @@ extern{
     lpdwNumDevs: *mut i32
 	) -> i32;
 
+  fn FT_GetDeviceInfo(
+    pHandle: *mut core::ffi::c_void,
+    lpftDevice: *mut u32,
+    lpdwID: *mut u32,
+    pcSerialNumber: *mut u8,
+    pcDescription: *mut u8,
+    pvDummy: *mut core::ffi::c_void
+  ) -> i32;
+
   fn FT_Close(
     pHandle: *mut core::ffi::c_void
   ) -> i32;
@@ -241,6 +255,24 @@ extern{
 		lpBytesWritten: *mut u32
   ) -> i32;
 
+  fn FT_Read(
+    pHandle: *mut core::ffi::c_void,
+		lpBuffer: *mut u8,
+		dwBytesToRead: u32,
+		lpBytesReturned: *mut u32
+  ) -> i32;
+
+  fn FT_GetQueueStatus(
+    pHandle: *mut core::ffi::c_void,
+    lpdwAmountInRxQueue: *mut u32
+  ) -> i32;
+
+  fn FT_SetTimeouts(
+    pHandle: *mut core::ffi::c_void,
+    dwReadTimeout: u32,
+    dwWriteTimeout: u32
+  ) -> i32;
+
   fn FT_Purge(
     pHandle: *mut core::ffi::c_void,
     dwMask: u32,
@@ -274,6 +306,26 @@ impl Device {
     }
     Ok(())
   }
+  pub fn info(&self) -> Result<DeviceInfo, FTError> {
+    if self.handle.is_null() { return Err(FTError::DeviceClosed) }
+
+    let e: i32;
+    let mut device_type: u32 = 0;
+    let mut id: u32 = 0;
+    let mut serial_number = [0u8; 16];
+    let mut description = [0u8; 64];
+    unsafe{
+      e = FT_GetDeviceInfo(self.handle, &mut device_type, &mut id,
+        serial_number.as_mut_ptr(), description.as_mut_ptr(), std::ptr::null_mut());
+    }
+    if e != 0 {
+      return Err(FTError::FTD2XXError(get_fterror(e)));
+    }
+    let serial_len = serial_number.iter().position(|&x| x==0).unwrap_or(16);
+    Ok(DeviceInfo {
+      serial_number: String::from_utf8_lossy(&serial_number[..serial_len]).to_string(),
+    })
+  }
   pub fn write(&self, data: &[u8]) -> Result<usize, FTError> {
     if self.handle.is_null() { return Err(FTError::DeviceClosed) }
 
@@ -289,6 +341,49 @@ impl Device {
 
     Ok(written as usize)
   }
+  // Returns number of bytes read, less than requested if the read timeout has passed.
+  pub fn read(&self, data: &mut [u8]) -> Result<usize, FTError> {
+    if self.handle.is_null() { return Err(FTError::DeviceClosed) }
+
+    let e: i32;
+    let mut read: u32 = 0;
+    unsafe{
+      e = FT_Read(self.handle, data.as_mut_ptr(), data.len() as u32, &mut read);
+    }
+    if e != 0 {
+      return Err(FTError::FTD2XXError(get_fterror(e)));
+    }
+
+    Ok(read as usize)
+  }
+  // Bytes waiting in the receive queue.
+  pub fn queue_status(&self) -> Result<usize, FTError> {
+    if self.handle.is_null() { return Err(FTError::DeviceClosed) }
+
+    let e: i32;
+    let mut queued: u32 = 0;
+    unsafe{
+      e = FT_GetQueueStatus(self.handle, &mut queued);
+    }
+    if e != 0 {
+      return Err(FTError::FTD2XXError(get_fterror(e)));
+    }
+
+    Ok(queued as usize)
+  }
+  pub fn set_timeouts(&self, read: std::time::Duration, write: std::time::Duration) -> Result<(), FTError> {
+    if self.handle.is_null() { return Err(FTError::DeviceClosed) }
+
+    let e: i32;
+    unsafe{
+      e = FT_SetTimeouts(self.handle, read.as_millis() as u32, write.as_millis() as u32);
+    }
+    if e != 0 {
+      return Err(FTError::FTD2XXError(get_fterror(e)));
+    }
+
+    Ok(())
+  }
   pub fn set_data_characteristics(&self, wl: WordLength, sb: StopBits, p: Parity) -> Result<(), FTError> {
     if self.handle.is_null() { return Err(FTError::DeviceClosed) }
 
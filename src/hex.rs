@@ -0,0 +1,12 @@
+pub fn encode(data: &[u8]) -> String {
+  data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn decode(s: &str) -> Option<Vec<u8>> {
+  if !s.len().is_multiple_of(2) {
+    return None;
+  }
+  (0..s.len()).step_by(2)
+    .map(|i| s.get(i..i+2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+    .collect()
+}
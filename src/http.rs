@@ -0,0 +1,279 @@
+use std::{io::Read, net::SocketAddr, sync::{Arc, Mutex, mpsc::SyncSender, atomic::{AtomicBool, Ordering}}, time::{Duration, Instant}};
+
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
+
+use crate::{auth::Auth, ingest::{Ingest, Update}, osc::Command, source::SourceId};
+
+// how often WebSocket clients get new values
+const STREAM_INTERVAL: Duration = Duration::from_millis(50);
+// unchanged streams are pinged that often
+const PING_INTERVAL: Duration = Duration::from_secs(5);
+const MAX_STREAMS: usize = 16;
+const MAX_BODY: u64 = 16384;
+
+// browser UI, built into the binary
+const ASSETS: &[(&str, &str, &str)] = &[
+  ("", "text/html; charset=utf-8", include_str!("ui/index.html")),
+  ("/app.js", "text/javascript; charset=utf-8", include_str!("ui/app.js")),
+  ("/style.css", "text/css; charset=utf-8", include_str!("ui/style.css")),
+];
+
+// Latest state of the universe for the HTTP API, published by the output loop.
+pub struct Snapshot {
+  // merged values of all sources, as set over the network
+  pub values: Vec<u8>,
+  // frame sent to the device
+  pub output: Vec<u8>,
+  pub blackout: bool,
+  pub status: Value,
+}
+
+pub type Monitor = Arc<Mutex<Snapshot>>;
+
+// Zero values until the first frame.
+pub fn monitor(size: usize) -> Monitor {
+  Arc::new(Mutex::new(Snapshot { values: vec![0; size], output: vec![0; size], blackout: false, status: Value::Null }))
+}
+
+// "/roscdmx/status" name value pairs as JSON object.
+pub fn status_json(msg: &rosc::OscMessage) -> Value {
+  let mut status = serde_json::Map::new();
+  for pair in msg.args.chunks(2) {
+    let (name, value) = match pair {
+      [rosc::OscType::String(n), v] => (n.clone(), v),
+      _ => continue,
+    };
+    let value = match value {
+      rosc::OscType::String(s) => json!(s),
+      rosc::OscType::Bool(b) => json!(b),
+      rosc::OscType::Long(l) => json!(l),
+      rosc::OscType::Int(i) => json!(i),
+      rosc::OscType::Double(d) => json!(d),
+      rosc::OscType::Float(f) => json!(f),
+      _ => Value::Null,
+    };
+    status.insert(name, value);
+  }
+  Value::Object(status)
+}
+
+fn respond(request: Request, code: u16, body: Value) {
+  let header = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap();
+  let response = Response::from_string(body.to_string()).with_status_code(StatusCode(code)).with_header(header);
+  if let Err(e) = request.respond(response) {
+    println!("Error sending HTTP response: {}", e);
+  }
+}
+
+fn asset(request: Request, content_type: &str, body: &'static str) {
+  let header = Header::from_bytes(&b"Content-Type"[..], content_type.as_bytes()).unwrap();
+  if let Err(e) = request.respond(Response::from_string(body).with_header(header)) {
+    println!("Error sending HTTP response: {}", e);
+  }
+}
+
+fn error(request: Request, code: u16, message: &str) {
+  respond(request, code, json!({ "error": message }));
+}
+
+fn query(url: &str, name: &str) -> Option<String> {
+  let (_, query) = url.split_once('?')?;
+  query.split('&')
+    .filter_map(|p| p.split_once('='))
+    .find(|(k, _)| *k == name)
+    .map(|(_, v)| v.to_string())
+}
+
+fn header<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+  request.headers().iter().find(|h| h.field.equiv(name)).map(|h| h.value.as_str())
+}
+
+// Channel values of a PUT: JSON array of 0-255, null keeps the channel.
+fn parse_values(body: &Value) -> Option<Vec<Option<u8>>> {
+  body.as_array()?.iter().map(|v| match v {
+    Value::Null => Some(None),
+    v => v.as_u64().and_then(|v| u8::try_from(v).ok()).map(Some),
+  }).collect()
+}
+
+// REST API and WebSocket stream of one universe. Writes go through the same queue as OSC.
+pub struct Api {
+  server: Server,
+  universe: i32,
+  size: usize,
+  auth: Auth,
+  ingest: Ingest,
+  monitor: Monitor,
+  // close flags of the running streams, oldest first
+  streams: Arc<Mutex<Vec<Arc<AtomicBool>>>>,
+}
+
+impl Api {
+  pub fn new(server: Server, universe: i32, size: usize, auth: Auth, queue: SyncSender<Update>, rate: f64, monitor: Monitor) -> Api {
+    Api {
+      server, universe, size, auth, monitor,
+      ingest: Ingest::new(queue, size, rate),
+      streams: Arc::new(Mutex::new(Vec::new())),
+    }
+  }
+  pub fn run(&mut self) {
+    loop {
+      self.ingest.flush();
+      match self.server.recv_timeout(Duration::from_millis(10)) {
+        Ok(Some(request)) => self.handle(request),
+        Ok(None) => continue,
+        Err(e) => {
+          println!("Error receiving HTTP request: {}", e);
+          std::process::exit(1);
+        }
+      }
+    }
+  }
+  fn handle(&mut self, request: Request) {
+    let addr = match request.remote_addr() {
+      Some(a) => *a,
+      None => return error(request, 403, "unknown address"),
+    };
+    if let Err(e) = self.auth.check_address(addr.ip()) {
+      println!("Rejected HTTP request from {}: {}", addr, e);
+      return error(request, 403, &e.to_string());
+    }
+    let url = request.url().to_string();
+    let path = url.split('?').next().unwrap_or("").trim_end_matches('/').to_string();
+    let universe = format!("/universes/{}", self.universe);
+    if let (Method::Get, Some((_, content_type, body))) = (request.method(), ASSETS.iter().find(|a| a.0 == path)) {
+      return asset(request, content_type, body);
+    }
+    match (request.method(), path.as_str()) {
+      (Method::Get, "/blackout") => {
+        let blackout = self.monitor.lock().unwrap().blackout;
+        respond(request, 200, json!(blackout));
+      },
+      (Method::Put, "/blackout") => self.put_blackout(request, addr),
+      (Method::Get, "/status") => {
+        let status = self.monitor.lock().unwrap().status.clone();
+        respond(request, 200, status);
+      },
+      (Method::Get, "/universes") => respond(request, 200, json!([self.universe])),
+      (_, p) if p.starts_with(&universe) => match (request.method().clone(), &p[universe.len()..]) {
+        (Method::Get, "/channels") => self.get_channels(request, &url),
+        (Method::Put, "/channels") => self.put_channels(request, &url, addr),
+        (Method::Get, "/ws") => self.stream(request),
+        _ => error(request, 404, "not found"),
+      },
+      _ => error(request, 404, "not found"),
+    }
+  }
+  // "start" and "count" of the channel range, whole universe by default
+  fn range(&self, url: &str, count: Option<usize>) -> Option<(usize, usize)> {
+    let start = match query(url, "start") {
+      Some(s) => s.parse::<usize>().ok()?,
+      None => 0,
+    };
+    let count = match (query(url, "count"), count) {
+      (Some(c), _) => c.parse::<usize>().ok()?,
+      (None, Some(c)) => c,
+      (None, None) => self.size.checked_sub(start)?,
+    };
+    match start.checked_add(count) {
+      Some(end) if end <= self.size => Some((start, count)),
+      _ => None,
+    }
+  }
+  fn get_channels(&self, request: Request, url: &str) {
+    let (start, count) = match self.range(url, None) {
+      Some(r) => r,
+      None => return error(request, 400, "out of range"),
+    };
+    let (values, output) = {
+      let snapshot = self.monitor.lock().unwrap();
+      (snapshot.values.get(start..start+count).map(|v| v.to_vec()), snapshot.output.get(start..start+count).map(|v| v.to_vec()))
+    };
+    match (values, output) {
+      (Some(values), Some(output)) => respond(request, 200, json!({ "start": start, "values": values, "output": output })),
+      _ => error(request, 400, "out of range"),
+    }
+  }
+  fn read_json(request: &mut Request) -> Option<Value> {
+    let mut body = String::new();
+    request.as_reader().take(MAX_BODY).read_to_string(&mut body).ok()?;
+    serde_json::from_str(&body).ok()
+  }
+  fn put_blackout(&mut self, mut request: Request, addr: SocketAddr) {
+    match Api::read_json(&mut request) {
+      Some(Value::Bool(on)) if self.ingest.push(SourceId::Http(addr.ip()), Command::Blackout(on)) => respond(request, 200, json!(on)),
+      Some(Value::Bool(_)) => error(request, 503, "too many requests"),
+      _ => error(request, 400, "body has to be true or false"),
+    }
+  }
+  fn put_channels(&mut self, mut request: Request, url: &str, addr: SocketAddr) {
+    let values = match Api::read_json(&mut request).as_ref().and_then(parse_values) {
+      Some(v) if !v.is_empty() => v,
+      _ => return error(request, 400, "body has to be an array of values 0-255 or null"),
+    };
+    let (start, _) = match self.range(url, Some(values.len())) {
+      Some(r) if r.1 == values.len() => r,
+      _ => return error(request, 400, "out of range"),
+    };
+    if !self.ingest.push(SourceId::Http(addr.ip()), Command::Set { address: start, values }) {
+      return error(request, 503, "too many requests");
+    }
+    respond(request, 200, json!({ "start": start }));
+  }
+  // Sends values, output and blackout state as JSON text messages whenever they change, and pings
+  // in between, so closed connections fail to write. Reading would block the writes, the stream
+  // isn't read; when all slots are taken, the oldest stream is closed for the new one.
+  fn stream(&self, request: Request) {
+    let key = match header(&request, "Sec-WebSocket-Key") {
+      Some(k) if header(&request, "Upgrade").is_some_and(|u| u.eq_ignore_ascii_case("websocket")) => k.to_string(),
+      _ => return error(request, 400, "WebSocket upgrade expected"),
+    };
+    let accept = tungstenite::handshake::derive_accept_key(key.as_bytes());
+    let response = Response::empty(StatusCode(101))
+      .with_header(Header::from_bytes(&b"Sec-WebSocket-Accept"[..], accept.as_bytes()).unwrap());
+    let addr = request.remote_addr().copied();
+    let stream = request.upgrade("websocket", response);
+    let monitor = self.monitor.clone();
+    let streams = self.streams.clone();
+    let closed = Arc::new(AtomicBool::new(false));
+    {
+      let mut streams = streams.lock().unwrap();
+      if streams.len() >= MAX_STREAMS {
+        streams.remove(0).store(true, Ordering::Relaxed);
+      }
+      streams.push(closed.clone());
+    }
+    std::thread::spawn(move || {
+      let mut ws = tungstenite::WebSocket::from_raw_socket(stream, tungstenite::protocol::Role::Server, None);
+      let mut last = Value::Null;
+      let mut next_ping = Instant::now() + PING_INTERVAL;
+      while !closed.load(Ordering::Relaxed) {
+        let message = {
+          let snapshot = monitor.lock().unwrap();
+          json!({ "values": snapshot.values, "output": snapshot.output, "blackout": snapshot.blackout })
+        };
+        let result = if message != last {
+          let result = ws.send(tungstenite::Message::Text(message.to_string()));
+          last = message;
+          result
+        } else if Instant::now() >= next_ping {
+          next_ping = Instant::now() + PING_INTERVAL;
+          ws.send(tungstenite::Message::Ping(Vec::new()))
+        } else {
+          Ok(())
+        };
+        if let Err(e) = result {
+          println!("WebSocket stream to {:?} closed: {}", addr, e);
+          break;
+        }
+        std::thread::sleep(STREAM_INTERVAL);
+      }
+      if closed.load(Ordering::Relaxed) {
+        ws.close(None).ok();
+        ws.flush().ok();
+      }
+      streams.lock().unwrap().retain(|s| !Arc::ptr_eq(s, &closed));
+    });
+  }
+}
@@ -0,0 +1,299 @@
+use std::{collections::{BTreeMap, HashMap, VecDeque}, sync::mpsc::{SyncSender, TrySendError}, time::{Duration, Instant}};
+
+use crate::{osc::Command, source::{SourceId, Sources}};
+
+pub const QUEUE_SIZE: usize = 1024;
+const BUCKET_EXPIRY: Duration = Duration::from_secs(60);
+// commands held back per source
+const MAX_HELD: usize = 256;
+
+pub struct Update {
+  pub source: SourceId,
+  pub cmd: Command,
+}
+
+struct Bucket {
+  tokens: f64,
+  last: Instant,
+}
+
+// Channel writes held back by the rate limit or a full queue, latest value wins.
+struct Writes {
+  set: Vec<Option<u8>>,
+  set16: BTreeMap<usize, u16>,
+}
+impl Writes {
+  fn new(size: usize) -> Writes {
+    Writes { set: vec![None; size], set16: BTreeMap::new() }
+  }
+  fn accepts(cmd: &Command) -> bool {
+    matches!(cmd, Command::Set { .. } | Command::Set16 { .. })
+  }
+  fn add(&mut self, cmd: &Command) {
+    match cmd {
+      Command::Set { address, values } => {
+        for (i, v) in values.iter().enumerate() {
+          if let (Some(v), Some(p)) = (v, self.set.get_mut(address + i)) {
+            *p = Some(*v);
+          }
+        }
+      },
+      Command::Set16 { address, values } => {
+        for (i, v) in values.iter().enumerate() {
+          if let Some(v) = v {
+            self.set16.insert(address + 2*i, *v);
+          }
+        }
+      },
+      _ => {},
+    }
+  }
+  fn take(self) -> Vec<Command> {
+    let mut cmds = Vec::new();
+    let first = self.set.iter().position(|v| v.is_some());
+    let last = self.set.iter().rposition(|v| v.is_some());
+    if let (Some(first), Some(last)) = (first, last) {
+      cmds.push(Command::Set { address: first, values: self.set[first..=last].to_vec() });
+    }
+    for (address, v) in self.set16 {
+      cmds.push(Command::Set16 { address, values: vec![Some(v)] });
+    }
+    cmds
+  }
+}
+
+// Held back commands of one source in order. Consecutive writes are coalesced,
+// other commands (blackout, fades, cues, releases...) are kept as they are.
+enum Held {
+  Writes(Writes),
+  // with the sender's own address, replies go there
+  Other(Update),
+}
+
+// Socket side of the bounded queue to the output loop.
+// Sources are keyed like in the merge, so an OSC sender changing ports shares one bucket.
+// Every source gets a token bucket of `rate` messages per second; commands over the limit,
+// or not fitting into the queue, are held back and sent later in order, writes coalesced.
+pub struct Ingest {
+  queue: SyncSender<Update>,
+  size: usize,
+  rate: f64,
+  buckets: HashMap<SourceId, Bucket>,
+  pending: BTreeMap<SourceId, VecDeque<Held>>,
+  pub coalesced: u64,
+  pub dropped: u64,
+}
+
+impl Ingest {
+  // rate 0 means unlimited
+  pub fn new(queue: SyncSender<Update>, size: usize, rate: f64) -> Ingest {
+    Ingest {
+      queue, size, rate,
+      buckets: HashMap::new(),
+      pending: BTreeMap::new(),
+      coalesced: 0,
+      dropped: 0,
+    }
+  }
+  fn take_token(&mut self, source: SourceId) -> bool {
+    if self.rate <= 0_f64 {
+      return true;
+    }
+    let now = Instant::now();
+    let rate = self.rate;
+    let b = self.buckets.entry(source).or_insert(Bucket { tokens: rate, last: now });
+    b.tokens = (b.tokens + now.saturating_duration_since(b.last).as_secs_f64() * rate).min(rate.max(1_f64));
+    b.last = now;
+    if b.tokens >= 1_f64 {
+      b.tokens -= 1_f64;
+      true
+    } else {
+      false
+    }
+  }
+  // Returns the update back if the queue is full.
+  fn send(&mut self, update: Update) -> Option<Update> {
+    match self.queue.try_send(update) {
+      Ok(()) => None,
+      Err(TrySendError::Full(u)) => Some(u),
+      Err(TrySendError::Disconnected(_)) => {
+        println!("Output loop has stopped");
+        std::process::exit(1);
+      },
+    }
+  }
+  fn hold(&mut self, key: SourceId, update: Update) -> bool {
+    let size = self.size;
+    let held = self.pending.entry(key).or_default();
+    if Writes::accepts(&update.cmd) {
+      if let Some(Held::Writes(w)) = held.back_mut() {
+        w.add(&update.cmd);
+        self.coalesced += 1;
+        return true;
+      }
+    }
+    // only a sender flooding commands gets here
+    if held.len() >= MAX_HELD {
+      self.dropped += 1;
+      return false;
+    }
+    if Writes::accepts(&update.cmd) {
+      let mut w = Writes::new(size);
+      w.add(&update.cmd);
+      held.push_back(Held::Writes(w));
+      self.coalesced += 1;
+    } else {
+      held.push_back(Held::Other(update));
+    }
+    true
+  }
+  // Returns false if the command was dropped.
+  pub fn push(&mut self, source: SourceId, cmd: Command) -> bool {
+    let key = Sources::key(source);
+    if self.pending.contains_key(&key) {
+      self.flush();
+    }
+    let update = Update { source, cmd };
+    // keep order with the commands still held back
+    if self.pending.contains_key(&key) || !self.take_token(key) {
+      return self.hold(key, update);
+    }
+    match self.send(update) {
+      Some(update) => self.hold(key, update),
+      None => true,
+    }
+  }
+  // Sends held back commands of the sources which have tokens again, call periodically.
+  pub fn flush(&mut self) {
+    let now = Instant::now();
+    self.buckets.retain(|_, b| now.saturating_duration_since(b.last) < BUCKET_EXPIRY);
+    let sources: Vec<SourceId> = self.pending.keys().copied().collect();
+    for source in sources {
+      if !self.take_token(source) {
+        continue;
+      }
+      let mut held = self.pending.remove(&source).unwrap();
+      // one held entry per token, a full queue leaves the rest for later
+      if let Some(entry) = held.pop_front() {
+        let updates = match entry {
+          Held::Writes(w) => w.take().into_iter().map(|cmd| Update { source, cmd }).collect(),
+          Held::Other(update) => vec![update],
+        };
+        let mut rest = Vec::new();
+        for update in updates {
+          if !rest.is_empty() {
+            rest.push(update);
+          } else if let Some(update) = self.send(update) {
+            rest.push(update);
+          }
+        }
+        for update in rest.into_iter().rev() {
+          held.push_front(match update {
+            update if Writes::accepts(&update.cmd) => {
+              let mut w = Writes::new(self.size);
+              w.add(&update.cmd);
+              Held::Writes(w)
+            },
+            update => Held::Other(update),
+          });
+        }
+      }
+      if !held.is_empty() {
+        self.pending.insert(source, held);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::mpsc::{sync_channel, Receiver};
+
+  fn osc(addr: &str) -> SourceId {
+    SourceId::Osc(addr.parse().unwrap())
+  }
+
+  fn set(address: usize, values: &[u8]) -> Command {
+    Command::Set { address, values: values.iter().map(|v| Some(*v)).collect() }
+  }
+
+  fn received(rx: &Receiver<Update>) -> Command {
+    rx.try_recv().expect("update sent").cmd
+  }
+
+  #[test]
+  fn writes_are_coalesced() {
+    let (tx, rx) = sync_channel(1);
+    let mut ingest = Ingest::new(tx, 8, 0.0);
+    let source = osc("10.0.0.1:9000");
+    assert!(ingest.push(source, set(0, &[1])));
+    // queue is full, held back
+    assert!(ingest.push(source, set(0, &[2])));
+    assert!(ingest.push(source, set(1, &[3])));
+    assert!(ingest.push(source, set(0, &[4])));
+    assert_eq!(ingest.coalesced, 3);
+    assert!(matches!(received(&rx), Command::Set { address: 0, values } if values == vec![Some(1)]));
+    ingest.flush();
+    assert!(matches!(received(&rx), Command::Set { address: 0, values } if values == vec![Some(4), Some(3)]));
+    assert!(rx.try_recv().is_err());
+  }
+
+  #[test]
+  fn held_commands_keep_their_order() {
+    let (tx, rx) = sync_channel(1);
+    let mut ingest = Ingest::new(tx, 8, 0.0);
+    let source = osc("10.0.0.1:9000");
+    assert!(ingest.push(source, set(0, &[1])));
+    assert!(ingest.push(source, Command::Blackout(true)));
+    assert!(ingest.push(source, set(2, &[9])));
+    assert!(ingest.push(source, Command::CueGo));
+    assert!(matches!(received(&rx), Command::Set { address: 0, .. }));
+    ingest.flush();
+    assert!(matches!(received(&rx), Command::Blackout(true)));
+    ingest.flush();
+    assert!(matches!(received(&rx), Command::Set { address: 2, .. }));
+    ingest.flush();
+    assert!(matches!(received(&rx), Command::CueGo));
+    ingest.flush();
+    assert!(rx.try_recv().is_err());
+    assert_eq!(ingest.dropped, 0);
+  }
+
+  #[test]
+  fn flooding_sender_is_dropped() {
+    let (tx, rx) = sync_channel(1);
+    let mut ingest = Ingest::new(tx, 8, 0.0);
+    let source = osc("10.0.0.1:9000");
+    assert!(ingest.push(source, Command::CueGo));
+    for _ in 0..MAX_HELD {
+      assert!(ingest.push(source, Command::CueBack));
+    }
+    assert!(!ingest.push(source, Command::CueBack));
+    assert!(!ingest.push(source, set(0, &[1])));
+    assert_eq!(ingest.dropped, 2);
+    // other senders still get through once the queue has room
+    assert!(matches!(received(&rx), Command::CueGo));
+    assert!(ingest.push(osc("10.0.0.2:9000"), Command::CueStop));
+  }
+
+  #[test]
+  fn ports_of_one_address_share_the_rate_limit() {
+    let (tx, rx) = sync_channel(16);
+    let mut ingest = Ingest::new(tx, 8, 1.0);
+    assert!(ingest.push(osc("10.0.0.1:9000"), set(0, &[1])));
+    assert!(ingest.push(osc("10.0.0.1:9001"), set(0, &[2])));
+    assert!(ingest.push(osc("10.0.0.1:9002"), Command::CueState));
+    assert!(ingest.push(osc("10.0.0.2:9000"), set(0, &[3])));
+    assert_eq!(ingest.pending.len(), 1);
+    assert_eq!(ingest.buckets.len(), 2);
+    // replies go to the port the command came from
+    match ingest.pending.values().next().and_then(|h| h.back()) {
+      Some(Held::Other(update)) => assert_eq!(update.source, osc("10.0.0.1:9002")),
+      _ => panic!("command not held"),
+    }
+    assert!(matches!(received(&rx), Command::Set { values, .. } if values == vec![Some(1)]));
+    assert!(matches!(received(&rx), Command::Set { values, .. } if values == vec![Some(3)]));
+    assert!(rx.try_recv().is_err());
+  }
+}
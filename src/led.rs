@@ -0,0 +1,36 @@
+// Packets for LED pixel controllers: DDP and WLED realtime UDP.
+
+pub const DDP_PORT: u16 = 4048;
+pub const WLED_PORT: u16 = 21324;
+// version 1, push: the frame is complete
+const DDP_FLAGS: u8 = 0x41;
+// RGB, 8 bits per channel
+const DDP_TYPE_RGB8: u8 = 0x0b;
+const DDP_DEFAULT_OUTPUT: u8 = 0x01;
+const WLED_DRGB: u8 = 2;
+const WLED_DNRGB: u8 = 4;
+// seconds WLED waits before going back to its own effects
+const WLED_TIMEOUT: u8 = 2;
+
+// Frame starting at `offset` bytes of the strip, sequence 1-15.
+pub fn ddp_packet(sequence: u8, offset: u32, data: &[u8]) -> Vec<u8> {
+  let mut buf = vec![DDP_FLAGS, sequence & 0x0f, DDP_TYPE_RGB8, DDP_DEFAULT_OUTPUT];
+  buf.extend_from_slice(&offset.to_be_bytes());
+  buf.extend_from_slice(&(data.len() as u16).to_be_bytes());
+  buf.extend_from_slice(data);
+  buf
+}
+
+// DRGB from the first LED, DNRGB from `start` otherwise; incomplete last pixel is left out.
+pub fn wled_packet(start: u16, data: &[u8]) -> Vec<u8> {
+  let data = &data[..data.len() - data.len() % 3];
+  let mut buf = if start == 0 {
+    vec![WLED_DRGB, WLED_TIMEOUT]
+  } else {
+    let mut b = vec![WLED_DNRGB, WLED_TIMEOUT];
+    b.extend_from_slice(&start.to_be_bytes());
+    b
+  };
+  buf.extend_from_slice(data);
+  buf
+}
@@ -1,19 +1,53 @@
-use std::{ops::Add, num::ParseIntError, process::exit, env::args};
+use std::{ops::Add, process::exit, env::args};
 
 use ftd2xx::Device;
 use windows::Win32::Media::timeBeginPeriod;
 
 mod ftd2xx;
 mod timer;
+mod patch;
+mod osc;
+mod fade;
+mod effects;
+mod scene;
+mod cue;
+mod master;
+mod source;
+mod mapping;
+mod safety;
+mod hex;
+mod auth;
+mod ingest;
+mod feedback;
+mod status;
+mod rdm;
+mod artnet;
+mod sacn;
+mod sync;
+mod led;
+mod bridge;
+mod http;
+mod mqtt;
+mod universe;
 
 const DMX_SIZE: usize = 512;
 const DMX_BAUDRATE: u32 = 250000;
 const DMX_BREAK_TIME: u64 = 92;
 const DMX_MAB_TIME: u64 = 12;
 const DMX_IDLE_TIME: u64 = 5000;
+// one slot at 250kbaud
+const DMX_SLOT_TIME: u64 = 44;
+// responder has to start answering within 2.8ms and can't pause for more than 2.1ms
+const RDM_RESPONSE_TIME: u64 = 2800;
+const RDM_INTER_SLOT_TIME: u64 = 2100;
+// RDM requests waiting for the output loop
+const RDM_QUEUE_SIZE: usize = 64;
+// at most one line per interval about rejected or dropped packets, all are counted
+const LOG_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
 
 struct DMX<'a> {
-  device: &'a ftd2xx::Device,
+  // None when only network outputs are driven
+  device: Option<&'a ftd2xx::Device>,
   timer: timer::Timer,
   pub break_time: std::time::Duration,
   pub mab_time: std::time::Duration,
@@ -22,9 +56,9 @@ struct DMX<'a> {
   pub data: Vec<u8>,
 }
 impl DMX<'_> {
-  fn new<'a>(device: &'a ftd2xx::Device, size: usize) -> DMX {
+  fn new(device: Option<&ftd2xx::Device>, size: usize) -> DMX<'_> {
     return DMX{
-        device: &device,
+        device,
         timer: timer::Timer::new(),
         break_time: std::time::Duration::from_micros(DMX_BREAK_TIME),
         mab_time: std::time::Duration::from_micros(DMX_MAB_TIME),
@@ -33,24 +67,64 @@ impl DMX<'_> {
         next: std::time::Instant::now(),
     };
   }
-  fn send_data(&mut self) -> Result<(), ftd2xx::FTError> {
-    self.device.set_break_on()?;
+  fn send_data(&mut self) -> Result<usize, ftd2xx::FTError> {
+    let device = match self.device {
+      Some(d) => d,
+      None => {
+        // keep the frame rate of a real line
+        let slots = std::time::Duration::from_micros(DMX_SLOT_TIME * self.data.len() as u64);
+        self.timer.sleep_for(self.break_time + self.mab_time + slots);
+        return Ok(self.data.len());
+      }
+    };
+    device.set_break_on()?;
     self.timer.sleep_for(self.break_time);
-    self.device.set_break_off()?;
+    device.set_break_off()?;
     self.timer.sleep_for(self.mab_time);
-    self.device.write(&self.data)?;
-    Ok(())
+    device.write(&self.data)
   }
-  fn wait_and_send(&mut self) -> Result<(), ftd2xx::FTError> {
+  fn wait_and_send(&mut self) -> Result<usize, ftd2xx::FTError> {
     self.timer.sleep(self.next);
     let ret = self.send_data();
     self.next = std::ops::Add::add(std::time::Instant::now(), self.idle_time);
     return ret;
   }
 }
-
-fn get_shift(starter: &str, addr:&str) -> Result<usize, ParseIntError> {
-  return addr[starter.len()..].parse::<usize>()
+impl rdm::Bus for DMX<'_> {
+  fn transaction(&mut self, packet: &[u8], response: bool) -> Result<Vec<u8>, ftd2xx::FTError> {
+    let device = self.device.ok_or(ftd2xx::FTError::DeviceClosed)?;
+    self.timer.sleep(self.next);
+    device.purge(ftd2xx::PurgeFlags::RX)?;
+    device.set_break_on()?;
+    self.timer.sleep_for(self.break_time);
+    device.set_break_off()?;
+    self.timer.sleep_for(self.mab_time);
+    device.write(packet)?;
+    let mut received = Vec::new();
+    if response {
+      let slots = std::time::Duration::from_micros(DMX_SLOT_TIME * packet.len() as u64);
+      let mut quiet_until = std::time::Instant::now() + slots + std::time::Duration::from_micros(RDM_RESPONSE_TIME);
+      loop {
+        let queued = device.queue_status()?;
+        if queued > 0 {
+          let start = received.len();
+          received.resize(start + queued, 0);
+          let read = device.read(&mut received[start..])?;
+          received.truncate(start + read);
+          quiet_until = std::time::Instant::now() + std::time::Duration::from_micros(RDM_INTER_SLOT_TIME);
+        } else if std::time::Instant::now() >= quiet_until {
+          break;
+        }
+        self.timer.sleep_for(std::time::Duration::from_micros(200));
+      }
+      // interfaces which hear their own transmission
+      if received.starts_with(packet) {
+        received.drain(..packet.len());
+      }
+    }
+    self.next = std::ops::Add::add(std::time::Instant::now(), self.idle_time);
+    Ok(received)
+  }
 }
 
 fn main() {
@@ -78,15 +152,106 @@ fn main() {
   let mut device_description = "".to_string();
   let mut device_location_index = -1;
 
+  let mut priority = source::DEFAULT_PRIORITY;
+    let priority_help = format!("priority of OSC senders (0-{}), default {}", source::MAX_PRIORITY, priority);
+  let mut source_timeout: f64 = 0.0;
+  let mut source_specs: Vec<String> = Vec::new();
+  let mut default_look = "".to_string();
+
+  let mut admin_addresses: Vec<String> = Vec::new();
+  let mut rate_limit: f64 = 0.0;
+  let mut heartbeat_target = "".to_string();
+  let mut heartbeat_interval: f64 = 1.0;
+    let heartbeat_interval_help = format!("heartbeat interval in seconds, default {}", heartbeat_interval);
+  let mut error_replies = false;
+  let mut feedback_targets: Vec<String> = Vec::new();
+  let mut feedback_rate: f64 = 10.0;
+    let feedback_rate_help = format!("how often feedback is sent at most, per second, default {}", feedback_rate);
+  let mut allowed_networks: Vec<String> = Vec::new();
+  let mut secret_file = "".to_string();
+  let mut artnet_universe = "".to_string();
+  let mut artnet_remote = false;
+  let mut sacn_universe: u16 = 0;
+  let mut sacn_interface = "0.0.0.0".to_string();
+  let mut sync_timeout: f64 = 4.0;
+    let sync_timeout_help = format!("seconds without ArtSync or sACN sync after which output runs freely, 0 ignores sync, default {}", sync_timeout);
+  let mut no_device = false;
+  let mut http_address = "".to_string();
+  let mut mqtt_broker = "".to_string();
+  let mut mqtt_id = "".to_string();
+  let mut mqtt_prefix = "roscdmx".to_string();
+    let mqtt_prefix_help = format!("first level of the MQTT topics, default {}", mqtt_prefix);
+  let mut outputs: Vec<String> = Vec::new();
+  let mut output_rate: f64 = 44.0;
+    let output_rate_help = format!("network output frames per second at most, default {}", output_rate);
+  let mut artnet_rdm = false;
+  let mut artnet_bind = format!("0.0.0.0:{}", artnet::PORT);
+    let artnet_bind_help = format!("address to receive Art-Net on, default {}", artnet_bind);
+
   let mut list_devices= false;
+  let mut patch_file = "".to_string();
+  let mut scene_file = "scenes.txt".to_string();
+    let scene_file_help = format!("file to store scenes in, empty to keep them in memory only, default {}", scene_file);
+  let mut show_file = "".to_string();
 
   {
     let mut ap = argparse::ArgumentParser::new();
     ap.set_description(r#"OSC driver for DMX USB based on FTD2XX chip.
 Listens to OSC DMX and streams it to DMX.
+With --artnet it is also an Art-Net node, every ArtDmx sender is merged as a separate source.
+The node answers ArtPoll with its name, Port-Address and the device serial and state.
+With --sacn it receives an E1.31 universe, every stream is a source with the priority it sends.
+With --output the frame is also sent to Art-Net or sACN nodes or DDP and WLED pixel controllers,
+--no_device sends it there only.
+Frames are held until ArtSync or sACN sync packets arrive once senders start sending them.
+With --http it serves a REST API, a WebSocket stream of channel values and a fader UI for browsers, see README.
+With --mqtt it connects to a broker, channels are set and published under <prefix>/<universe>/<channel>/.
+With --rdm it also proxies ArtTodRequest, ArtTodControl and ArtRdm to RDM devices on the line.
+Each sender gets its own buffer, merged HTP or LTP per channel as set in the patch file.
+Only senders with the highest priority are merged, others take over when they time out.
   - OSC messages will be:
     * Address: "/<universe_number>/dmx/<dmx_address>"
     * Data: <list of integers> -> part of updated DMX data, starting from the dmx_address
+  - 16 bit values (coarse/fine pairs):
+    * Address: "/<universe_number>/dmx16/<dmx_address>"
+    * Data: <list of floats or integers> -> 16 bit values, each taking two channels
+      (MSB first unless declared otherwise in the patch file)
+  - Fades, running at the DMX frame rate:
+    * Address: "/<universe_number>/fade/<dmx_address>" or "/<universe_number>/fade16/<dmx_address>"
+    * Data: <list of target values> <seconds> [<profile>] -> profile is "linear" (default) or "scurve",
+      new fades and values replace running fades on the same channels
+  - Effects, composited over the received values:
+    * Address: "/<universe_number>/fx/<name>"
+    * Data: <waveform> [<blend>] <rate Hz> <spread> <min> <max> <list of channels>
+      waveform is "sine", "saw", "square" or "random", blend is "htp" (default), "add" or "scale",
+      spread is the phase offset across the channel list in cycles
+    * "/<universe_number>/fx/<name>/start", ".../stop" and ".../remove" control defined effects
+  - Scenes, stored in the scene file:
+    * "/scene/record <name>" stores current DMX data
+    * "/scene/recall <name> [<seconds>]" crossfades to the stored scene
+  - Cue list from the show file:
+    * "/cue/go", "/cue/back", "/cue/goto <number>", "/cue/stop" (also releases recalled scenes)
+    * "/cue/state" replies with "/cue/state <number> <scene> <state>"
+  - Masters, scaling intensity channels declared in the patch file:
+    * "/master <level>" for the grand master, "/sub/<name> <level>" for submasters
+      (level is float 0-1 or integer 0-255)
+    * "/blackout on|off" holds all outputs but parked slots and limit minimums at zero, keeping received values
+  - Feedback of changed channel values as bundles of "/<universe_number>/dmx/<dmx_address> <value>":
+    * "/subscribe <port>" sends it to the port on the sender's address for a minute, renewed by subscribing again,
+      "/unsubscribe [<port>]" stops; accepted only from --allow addresses or signed, or from localhost and --admin
+  - "/roscdmx/status" replies with "/roscdmx/status" and <name> <value> pairs: device serial, open state,
+    frame rate, written frames, failed and short writes, last device error, OSC packets received,
+    rejected, dropped, coalesced and malformed, ArtDmx and sACN packets received, uptime; the same message is sent to --heartbeat target
+  - Messages with unknown address, wrong argument types or channels past the universe size are
+    dropped as a whole; with --error_replies the sender gets "/roscdmx/error <reason> <address>"
+  - Bundles are unpacked and their messages handled one by one
+  - With --secret_file, every message has to carry HMAC-SHA256 of the message without it as
+    the last argument (32 byte blob or hex string), and before it the sender's clock as int64
+    milliseconds since the Unix epoch, increasing with every message and within 10 seconds
+  - Admin namespace, accepted from localhost and --admin addresses only:
+    * "/roscdmx/admin/limit <slot> <min> <max>" (output slot, after curves and routing)
+    * "/roscdmx/admin/park <slot> <value>", "/roscdmx/admin/unpark <slot>"
+    * "/roscdmx/admin/lock <channel>", "/roscdmx/admin/unlock <channel>" (locked channels ignore writes)
 "#);
     
     ap.refer(&mut dmx_size)
@@ -113,6 +278,66 @@ Listens to OSC DMX and streams it to DMX.
       .add_option(&["-D", "--device_description"], argparse::Store, "select FTD2XX device by its description instead of index");
     ap.refer(&mut device_location_index)
       .add_option(&["-l", "--device_location"], argparse::Store, "select FTD2XX device by its location index instead of index");
+    ap.refer(&mut patch_file)
+      .add_option(&["-P", "--patch"], argparse::Store, "patch file declaring 16 bit attributes, intensity channels, submasters, channel routing and curves");
+    ap.refer(&mut scene_file)
+      .add_option(&["-S", "--scenes"], argparse::Store, &scene_file_help);
+    ap.refer(&mut show_file)
+      .add_option(&["-C", "--show"], argparse::Store, "show file with the cue list");
+    ap.refer(&mut priority)
+      .add_option(&["--priority"], argparse::Store, &priority_help);
+    ap.refer(&mut source_timeout)
+      .add_option(&["--source_timeout"], argparse::Store, "release values of OSC senders silent for that many seconds, default 0 (never)");
+    ap.refer(&mut source_specs)
+      .add_option(&["--source"], argparse::Collect, "priority and timeout of a sender: <ip>=<priority>[/<timeout seconds>], may be repeated");
+    ap.refer(&mut default_look)
+      .add_option(&["--default_look"], argparse::Store, "scene to show when no source is active");
+    ap.refer(&mut admin_addresses)
+      .add_option(&["--admin"], argparse::Collect, "IP address allowed to use the admin namespace besides localhost, may be repeated");
+    ap.refer(&mut allowed_networks)
+      .add_option(&["--allow"], argparse::Collect, "accept OSC, Art-Net, sACN and HTTP only from this address or network (CIDR), may be repeated, default accepts everyone");
+    ap.refer(&mut secret_file)
+      .add_option(&["--secret_file"], argparse::Store, "file with the shared secret, turns on HMAC-SHA256 signed messages");
+    ap.refer(&mut rate_limit)
+      .add_option(&["--rate_limit"], argparse::Store, "messages per second accepted from one sender address, messages above it are delayed in order and channel writes coalesced, default 0 (unlimited)");
+    ap.refer(&mut feedback_targets)
+      .add_option(&["--feedback"], argparse::Collect, "<host>:<port> to always send feedback to, may be repeated");
+    ap.refer(&mut feedback_rate)
+      .add_option(&["--feedback_rate"], argparse::Store, &feedback_rate_help);
+    ap.refer(&mut heartbeat_target)
+      .add_option(&["--heartbeat"], argparse::Store, "<host>:<port> to send status to periodically");
+    ap.refer(&mut heartbeat_interval)
+      .add_option(&["--heartbeat_interval"], argparse::Store, &heartbeat_interval_help);
+    ap.refer(&mut artnet_universe)
+      .add_option(&["--artnet"], argparse::Store, "receive ArtDmx for this Port-Address, <net>:<sub-net>:<universe> or a number");
+    ap.refer(&mut artnet_bind)
+      .add_option(&["--artnet_bind"], argparse::Store, &artnet_bind_help);
+    ap.refer(&mut artnet_remote)
+      .add_option(&["--artnet_remote"], argparse::StoreTrue, "accept ArtAddress renaming and re-mapping the node, and RDM SET and discovery flush, from localhost and --admin addresses");
+    ap.refer(&mut artnet_rdm)
+      .add_option(&["--rdm"], argparse::StoreTrue, "proxy Art-Net RDM to the DMX line, needs an RDM capable interface");
+    ap.refer(&mut sacn_universe)
+      .add_option(&["--sacn"], argparse::Store, "receive this sACN (E1.31) universe, 1-63999");
+    ap.refer(&mut sacn_interface)
+      .add_option(&["--sacn_interface"], argparse::Store, "IPv4 address of the interface to join sACN multicast on, default any");
+    ap.refer(&mut sync_timeout)
+      .add_option(&["--sync_timeout"], argparse::Store, &sync_timeout_help);
+    ap.refer(&mut outputs)
+      .add_option(&["--output"], argparse::Collect, "also send the output as artnet:<Port-Address>@<host>[:<port>], sacn:<universe>[@<host>[:<port>]] (multicast without host), ddp:[<start pixel>@]<host>[:<port>] or wled:[<start pixel>@]<host>[:<port>], may be repeated");
+    ap.refer(&mut output_rate)
+      .add_option(&["--output_rate"], argparse::Store, &output_rate_help);
+    ap.refer(&mut no_device)
+      .add_option(&["--no_device"], argparse::StoreTrue, "don't open FTD2XX device, drive --output targets only or just serve --mqtt and --http");
+    ap.refer(&mut http_address)
+      .add_option(&["--http"], argparse::Store, "<address>:<port> to serve the HTTP API on, accepts --allow addresses only (required with --secret_file), default off");
+    ap.refer(&mut mqtt_broker)
+      .add_option(&["--mqtt"], argparse::Store, "<host>[:<port>] or [<IPv6 address>]:<port> of the MQTT broker to connect to, default off");
+    ap.refer(&mut mqtt_id)
+      .add_option(&["--mqtt_id"], argparse::Store, "MQTT client ID, default roscdmx-<universe>");
+    ap.refer(&mut mqtt_prefix)
+      .add_option(&["--mqtt_prefix"], argparse::Store, &mqtt_prefix_help);
+    ap.refer(&mut error_replies)
+      .add_option(&["--error_replies"], argparse::StoreTrue, "reply \"/roscdmx/error <reason> <address>\" to malformed messages");
     ap.refer(&mut list_devices)
       .add_option(&["-L", "--list_devices"], argparse::StoreTrue, "list all available FTD2XX devices");
 
@@ -146,77 +371,415 @@ Listens to OSC DMX and streams it to DMX.
       println!("DMX MAB time has to be between 1us and 1000000us.");
       std::process::exit(1);
     }
+    if priority > source::MAX_PRIORITY {
+      println!("Priority has to be between 0 and {}.", source::MAX_PRIORITY);
+      std::process::exit(1);
+    }
+    if !rate_limit.is_finite() || rate_limit < 0.0 {
+      println!("Rate limit has to be a positive number.");
+      std::process::exit(1);
+    }
+    // intervals have to fit into a Duration
+    if !feedback_rate.is_finite() || feedback_rate <= 0.0 || std::time::Duration::try_from_secs_f64(1.0 / feedback_rate).is_err() {
+      println!("Feedback rate has to be a positive number.");
+      std::process::exit(1);
+    }
+    if heartbeat_interval <= 0.0 || std::time::Duration::try_from_secs_f64(heartbeat_interval).is_err() {
+      println!("Heartbeat interval has to be a positive number.");
+      std::process::exit(1);
+    }
+    if !output_rate.is_finite() || output_rate <= 0.0 || std::time::Duration::try_from_secs_f64(1.0 / output_rate).is_err() {
+      println!("Output rate has to be a positive number.");
+      std::process::exit(1);
+    }
+    // MQTT and HTTP clients can watch the values without any output
+    if no_device && outputs.is_empty() && mqtt_broker.is_empty() && http_address.is_empty() {
+      println!("Without device there has to be at least one --output, --mqtt or --http.");
+      std::process::exit(1);
+    }
+    if no_device && artnet_rdm {
+      println!("RDM needs the device.");
+      std::process::exit(1);
+    }
+    if source::seconds(sync_timeout).is_none() {
+      println!("Sync timeout has to be a positive number.");
+      std::process::exit(1);
+    }
+    if sacn_universe > sacn::MAX_UNIVERSE {
+      println!("sACN universe has to be between 1 and {}.", sacn::MAX_UNIVERSE);
+      std::process::exit(1);
+    }
     if dmx_idle_time < 1 || dmx_idle_time > 10000000000 {
       println!("DMX idle time has to be between 1us and 10000000000us.");
       std::process::exit(1);
     }
   }
 
-  let dev = if device_location_index>=0 {
-    ftd2xx::Device::open_by_location(device_location_index).unwrap()
+  let patch = if !patch_file.is_empty() {
+    match patch::Patch::load(&patch_file) {
+      Ok(p) => p,
+      Err(e) => {
+        println!("{}", e);
+        std::process::exit(1);
+      }
+    }
+  } else {
+    patch::Patch::new()
+  };
+
+  let scenes = match scene::Scenes::load(&scene_file) {
+    Ok(s) => s,
+    Err(e) => {
+      println!("{}", e);
+      std::process::exit(1);
+    }
+  };
+
+  let mut sources = source::SourceConfig {
+    default: source::SourceSettings {
+      priority,
+      timeout: match source::seconds(source_timeout) {
+        Some(t) => t,
+        None => {
+          println!("Source timeout has to be a positive number of seconds.");
+          std::process::exit(1);
+        }
+      },
+    },
+    per_ip: std::collections::HashMap::new(),
+  };
+  for spec in &source_specs {
+    if sources.add_spec(spec).is_none() {
+      println!("Bad source {:?}, expected <ip>=<priority>[/<timeout seconds>].", spec);
+      std::process::exit(1);
+    }
+  }
+
+  let mut admins: Vec<std::net::IpAddr> = Vec::new();
+  for a in &admin_addresses {
+    match a.parse() {
+      Ok(ip) => admins.push(ip),
+      Err(e) => {
+        println!("Bad admin address {:?}: {}", a, e);
+        std::process::exit(1);
+      }
+    }
+  }
+
+  let mut allow: Vec<auth::Cidr> = Vec::new();
+  for n in &allowed_networks {
+    match auth::Cidr::parse(n) {
+      Some(c) => allow.push(c),
+      None => {
+        println!("Bad allowed network {:?}", n);
+        std::process::exit(1);
+      }
+    }
+  }
+  let secret = if !secret_file.is_empty() {
+    match std::fs::read_to_string(&secret_file) {
+      Ok(s) if s.trim() != "" => Some(s.trim().as_bytes().to_vec()),
+      Ok(_) => {
+        println!("Secret file {} is empty", secret_file);
+        std::process::exit(1);
+      },
+      Err(e) => {
+        println!("Can't read secret file {}: {}", secret_file, e);
+        std::process::exit(1);
+      }
+    }
+  } else {
+    None
+  };
+  let http_auth = auth::Auth::new(allow.clone(), None);
+  let artnet_auth = auth::Auth::new(allow.clone(), None);
+  let sacn_auth = auth::Auth::new(allow.clone(), None);
+  let mut auth = auth::Auth::new(allow, secret);
+
+  let cues = if !show_file.is_empty() {
+    match cue::CueList::load(&show_file) {
+      Ok(c) => c,
+      Err(e) => {
+        println!("{}", e);
+        std::process::exit(1);
+      }
+    }
+  } else {
+    cue::CueList::new()
+  };
+
+  let dev = if no_device {
+    None
+  } else if device_location_index>=0 {
+    Some(ftd2xx::Device::open_by_location(device_location_index).unwrap())
   } else if device_serial != "" {
-    ftd2xx::Device::open_by_serial(&device_serial).unwrap()
+    Some(ftd2xx::Device::open_by_serial(&device_serial).unwrap())
   } else if device_description != "" {
-    ftd2xx::Device::open_by_description(&device_description).unwrap()
+    Some(ftd2xx::Device::open_by_description(&device_description).unwrap())
   } else {
-    ftd2xx::Device::open(device_index).unwrap()
+    Some(ftd2xx::Device::open(device_index).unwrap())
   };
-  dev.reset().unwrap();
-  dev.set_data_characteristics(ftd2xx::WordLength::Bits8, ftd2xx::StopBits::Bits2, ftd2xx::Parity::None).unwrap();
-  dev.set_flow_control(ftd2xx::FlowControl::None).unwrap();
-  dev.set_baud_rate(dmx_baudrate).unwrap();
-  dev.set_latency_timer(2).unwrap();
-  dev.purge(ftd2xx::PurgeFlags::RX | ftd2xx::PurgeFlags::TX).unwrap();
-  dev.clear_rts().unwrap();
+  if let Some(dev) = &dev {
+    dev.reset().unwrap();
+    dev.set_data_characteristics(ftd2xx::WordLength::Bits8, ftd2xx::StopBits::Bits2, ftd2xx::Parity::None).unwrap();
+    dev.set_flow_control(ftd2xx::FlowControl::None).unwrap();
+    dev.set_baud_rate(dmx_baudrate).unwrap();
+    dev.set_latency_timer(2).unwrap();
+    if artnet_rdm {
+      // reads never wait longer than a response may take, writes keep no timeout
+      dev.set_timeouts(std::time::Duration::from_millis(3), std::time::Duration::ZERO).unwrap();
+    }
+    dev.purge(ftd2xx::PurgeFlags::RX | ftd2xx::PurgeFlags::TX).unwrap();
+    dev.clear_rts().unwrap();
+  }
   let addr_port_str = addr.to_owned() + ":" + &port.to_string();
-  let osc_address_starter = "/".to_owned() + &universe.to_string() + "/dmx/";
+  let osc_address_starter = "/".to_owned() + &universe.to_string() + "/";
 
-  let mut dmx = DMX::new(&dev, dmx_size);
-  let data = std::sync::Arc::new(std::sync::Mutex::new(vec![0;dmx_size]));
-  let data_t = data.clone();
+  let mut dmx = DMX::new(dev.as_ref(), dmx_size);
+  let resolve = |t: &str, what: &str| -> std::net::SocketAddr {
+    match std::net::ToSocketAddrs::to_socket_addrs(t).map(|mut a| a.next()) {
+      Ok(Some(a)) => a,
+      _ => {
+        println!("Bad {} {:?}", what, t);
+        std::process::exit(1);
+      }
+    }
+  };
+  let targets: Vec<std::net::SocketAddr> = feedback_targets.iter().map(|t| resolve(t, "feedback target")).collect();
+  let heartbeat = if !heartbeat_target.is_empty() {
+    Some(resolve(&heartbeat_target, "heartbeat target"))
+  } else {
+    None
+  };
+  let heartbeat_interval = std::time::Duration::from_secs_f64(heartbeat_interval);
+  let feedback = feedback::Feedback::new(&osc_address_starter, feedback_rate, targets);
+  let mut dmx_universe = universe::Universe::new(dmx_size, patch, scenes, cues, sources, feedback);
+  if !default_look.is_empty() && !dmx_universe.set_default_look(&default_look) {
+    println!("Default look scene {} not found.", default_look);
+    std::process::exit(1);
+  }
+  let sock = std::net::UdpSocket::bind(addr_port_str).unwrap();
+  sock.set_read_timeout(Some(std::time::Duration::from_millis(10))).unwrap();
+  let reply_sock = sock.try_clone().unwrap();
+  let (queue, updates) = std::sync::mpsc::sync_channel::<ingest::Update>(ingest::QUEUE_SIZE);
+  let stats = std::sync::Arc::new(status::Stats::default());
+  let stats_t = stats.clone();
+  let serial = match dev.as_ref().map(|d| d.info()) {
+    Some(Ok(info)) => info.serial_number,
+    Some(Err(e)) => {
+      println!("Can't get device info: {}", e);
+      "".to_string()
+    },
+    None => "".to_string(),
+  };
+  let mut health = status::Health::new(serial.clone());
+  let mut next_heartbeat = std::time::Instant::now();
+  let mut targets: Vec<bridge::Target> = Vec::new();
+  for o in &outputs {
+    match bridge::Target::parse(o) {
+      Some(bridge::Target { protocol: bridge::Protocol::Sacn(u), .. }) if u == sacn_universe => {
+        println!("Output {:?} would loop back into --sacn universe", o);
+        std::process::exit(1);
+      },
+      Some(bridge::Target { protocol: bridge::Protocol::ArtNet(p), .. }) if Some(p) == artnet::PortAddress::parse(&artnet_universe) => {
+        println!("Output {:?} would loop back into --artnet Port-Address", o);
+        std::process::exit(1);
+      },
+      Some(t) => targets.push(t),
+      None => {
+        println!("Bad output {:?}", o);
+        std::process::exit(1);
+      }
+    }
+  }
+  let mut bridge = if targets.is_empty() {
+    None
+  } else {
+    let bridge_sock = std::net::UdpSocket::bind("0.0.0.0:0").unwrap();
+    bridge_sock.set_broadcast(true).unwrap();
+    Some(bridge::Bridge::new(bridge_sock, targets, sacn::cid(&format!("roscdmx {} {}", serial, universe)), output_rate))
+  };
+  let mut synchronizer = sync::Synchronizer::new(source::seconds(sync_timeout).unwrap());
+  let mut frame = vec![0u8; dmx_size];
+
+  let mut rdm_port: Option<(artnet::RdmPort, std::sync::mpsc::Receiver<artnet::RdmRequest>)> = None;
+  if artnet_universe.is_empty() && artnet_rdm {
+    println!("RDM needs --artnet.");
+    std::process::exit(1);
+  }
+  if !artnet_universe.is_empty() {
+    let port_address = match artnet::PortAddress::parse(&artnet_universe) {
+      Some(a) => a,
+      None => {
+        println!("Bad Art-Net Port-Address {:?}", artnet_universe);
+        std::process::exit(1);
+      }
+    };
+    let artnet_sock = match std::net::UdpSocket::bind(&artnet_bind) {
+      Ok(s) => s,
+      Err(e) => {
+        println!("Can't listen for Art-Net on {}: {}", artnet_bind, e);
+        std::process::exit(1);
+      }
+    };
+    artnet_sock.set_read_timeout(Some(std::time::Duration::from_millis(10))).unwrap();
+    let ingest = ingest::Ingest::new(queue.clone(), dmx_size, 0.0);
+    let mut node = artnet::Node::new(artnet_sock.try_clone().unwrap(), port_address, dmx_size, artnet_auth, ingest, stats.clone(), serial.clone());
+    if artnet_remote {
+      node.allow_remote(admins.clone());
+    }
+    if artnet_rdm {
+      let (requests, received) = std::sync::mpsc::sync_channel(RDM_QUEUE_SIZE);
+      node.enable_rdm(requests);
+      let controller = rdm::Controller::new(rdm::Uid::controller(&serial));
+      let mut port = artnet::RdmPort::new(artnet_sock.try_clone().unwrap(), controller);
+      if let Err(e) = port.discover(&mut dmx) {
+        println!("Can't start RDM discovery: {}", e);
+      }
+      rdm_port = Some((port, received));
+    }
+    println!("Receiving Art-Net universe {} on {}", port_address, artnet_bind);
+    std::thread::spawn(move || node.run());
+  }
+
+  if sacn_universe != 0 {
+    let interface = match sacn_interface.parse::<std::net::Ipv4Addr>() {
+      Ok(i) => i,
+      Err(_) => {
+        println!("Bad sACN interface {:?}", sacn_interface);
+        std::process::exit(1);
+      }
+    };
+    let sacn_sock = match std::net::UdpSocket::bind(("0.0.0.0", sacn::PORT)) {
+      Ok(s) => s,
+      Err(e) => {
+        println!("Can't listen for sACN: {}", e);
+        std::process::exit(1);
+      }
+    };
+    let group = sacn::multicast_group(sacn_universe);
+    if let Err(e) = sacn_sock.join_multicast_v4(&group, &interface) {
+      println!("Can't join sACN multicast group {}: {}", group, e);
+      std::process::exit(1);
+    }
+    sacn_sock.set_read_timeout(Some(std::time::Duration::from_millis(10))).unwrap();
+    let ingest = ingest::Ingest::new(queue.clone(), dmx_size, 0.0);
+    let mut receiver = sacn::Receiver::new(sacn_sock, sacn_universe, interface, dmx_size, sacn_auth, ingest, stats.clone());
+    println!("Receiving sACN universe {} on {}", sacn_universe, group);
+    std::thread::spawn(move || receiver.run());
+  }
+
+  let monitor = http::monitor(dmx_size);
+  let mut next_status = std::time::Instant::now();
+  if !http_address.is_empty() && !secret_file.is_empty() && allowed_networks.is_empty() {
+    // HTTP writes aren't signed, they would bypass the secret
+    println!("--http with --secret_file needs --allow.");
+    std::process::exit(1);
+  }
+  if !http_address.is_empty() {
+    let server = match tiny_http::Server::http(&http_address) {
+      Ok(s) => s,
+      Err(e) => {
+        println!("Can't serve HTTP on {}: {}", http_address, e);
+        std::process::exit(1);
+      }
+    };
+    let mut api = http::Api::new(server, universe, dmx_size, http_auth, queue.clone(), rate_limit, monitor.clone());
+    println!("Serving HTTP API on {}", http_address);
+    std::thread::spawn(move || api.run());
+  }
+
+  if !mqtt_broker.is_empty() {
+    let broker = match mqtt::parse_broker(&mqtt_broker) {
+      Some(b) => b,
+      None => {
+        println!("Bad MQTT broker {:?}", mqtt_broker);
+        std::process::exit(1);
+      }
+    };
+    if mqtt_id.is_empty() {
+      mqtt_id = format!("roscdmx-{}", universe);
+    }
+    let mut client = mqtt::Mqtt::new(&mqtt_id, broker, &mqtt_prefix, universe, dmx_size, queue.clone(), monitor.clone());
+    println!("Connecting to MQTT broker {} as {}", mqtt_broker, mqtt_id);
+    std::thread::spawn(move || client.run());
+  }
 
   let osc_thread = std::thread::spawn(move ||{
-    let sock = std::net::UdpSocket::bind(addr_port_str).unwrap();
     let mut buf = [0u8; rosc::decoder::MTU];
+    let mut ingest = ingest::Ingest::new(queue, dmx_size, rate_limit);
+    let parser = osc::Parser::new(&osc_address_starter, dmx_size);
+    let report = |sock: &std::net::UdpSocket, addr: std::net::SocketAddr, e: osc::ParseError, osc_addr: &str| {
+      stats_t.errors.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+      if !error_replies {
+        return;
+      }
+      let reply = rosc::OscPacket::Message(rosc::OscMessage {
+        addr: "/roscdmx/error".to_string(),
+        args: vec![rosc::OscType::String(e.to_string()), rosc::OscType::String(osc_addr.to_string())],
+      });
+      if let Ok(buf) = rosc::encoder::encode(&reply) {
+        if let Err(e) = sock.send_to(&buf, addr) {
+          println!("Error replying to {}: {}", addr, e);
+        }
+      }
+    };
 
+    let mut next_reject_log = std::time::Instant::now();
+    let mut next_drop_log = std::time::Instant::now();
     loop {
+      ingest.flush();
       match sock.recv_from(&mut buf) {
         Ok((size, addr)) => {
-          let packet_err = rosc::decoder::decode_udp(&buf[..size]);
-          match packet_err {
-            Ok((_, packet)) => match packet {
-              rosc::OscPacket::Message(msg) if msg.addr.starts_with(&osc_address_starter) => {
-                match get_shift(&osc_address_starter, &msg.addr) {
-                  Ok(shift) => {
-                    let a_size = if shift + msg.args.len() < dmx_size {
-                      msg.args.len()
-                    } else {
-                      dmx_size-shift
-                    };
-                    {
-                      let mut data_u = data.lock().unwrap();
-                      for i in 0..a_size {
-                        (*data_u)[i+shift] = match msg.args[i] {
-                          rosc::OscType::Int(a) => a.clamp(0, 0xff) as u8,
-                          rosc::OscType::Long(a) => a.clamp(0, 0xff) as u8,
-                          rosc::OscType::Float(f) => (f.clamp(0_f32, 1_f32)*255_f32) as u8,
-                          rosc::OscType::Double(f) => (f.clamp(0_f64, 1_f64)*255_f64) as u8,
-                          rosc::OscType::Char(a) => a as u8,
-                          rosc::OscType::Bool(b) => b as u8,
-                          _ => (*data_u)[i+shift],
-                        };
-                      }
-                    }
-                  },
-                  _ => continue,
+          stats_t.received.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+          if let Err(e) = auth.check_address(addr.ip()) {
+            let rejected = stats_t.rejected.fetch_add(1, std::sync::atomic::Ordering::Relaxed) + 1;
+            if std::time::Instant::now() >= next_reject_log {
+              next_reject_log = std::time::Instant::now() + LOG_INTERVAL;
+              println!("Rejected packet from {}: {} ({} rejected)", addr, e, rejected);
+            }
+            continue;
+          }
+          let packet = match rosc::decoder::decode_udp(&buf[..size]) {
+            Ok((_, packet)) => packet,
+            Err(_) => {
+              report(&sock, addr, osc::ParseError::Decode, "");
+              continue;
+            }
+          };
+          for msg in osc::messages(packet) {
+            let msg = match auth.verify(addr.ip(), msg) {
+              Ok(msg) => msg,
+              Err(e) => {
+                let rejected = stats_t.rejected.fetch_add(1, std::sync::atomic::Ordering::Relaxed) + 1;
+                if std::time::Instant::now() >= next_reject_log {
+                  next_reject_log = std::time::Instant::now() + LOG_INTERVAL;
+                  println!("Rejected message from {}: {} ({} rejected)", addr, e, rejected);
                 }
+                continue;
               }
-              _ => continue
+            };
+            match parser.parse(&msg) {
+              Ok(cmd) if cmd.is_admin() && !addr.ip().is_loopback() && !admins.contains(&addr.ip()) => {
+                println!("Admin command from {} rejected", addr);
+              },
+              // the sender address can't be trusted without --allow or --secret_file
+              Ok(osc::Command::Subscribe(_)) if auth.is_open() && !addr.ip().is_loopback() && !admins.contains(&addr.ip()) => {
+                println!("Subscription from {} rejected, needs --allow or --secret_file", addr);
+              },
+              Ok(cmd) => {
+                if !ingest.push(source::SourceId::Osc(addr), cmd) && std::time::Instant::now() >= next_drop_log {
+                  next_drop_log = std::time::Instant::now() + LOG_INTERVAL;
+                  println!("Rate limit: dropped message from {} ({} dropped, {} coalesced)", addr, ingest.dropped, ingest.coalesced);
+                }
+                stats_t.dropped.store(ingest.dropped, std::sync::atomic::Ordering::Relaxed);
+                stats_t.coalesced.store(ingest.coalesced, std::sync::atomic::Ordering::Relaxed);
+              },
+              Err(e) => report(&sock, addr, e, &msg.addr),
             }
-            _ => continue
           }
-          
         }
+        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock || e.kind() == std::io::ErrorKind::TimedOut => continue,
         Err(e) => {
           println!("Error receiving from socket: {}", e);
           std::process::exit(1);
@@ -226,10 +789,76 @@ Listens to OSC DMX and streams it to DMX.
   });
   
   loop {
+    let mut synced = false;
+    for update in updates.try_iter().take(ingest::QUEUE_SIZE) {
+      if let osc::Command::Sync = update.cmd {
+        // later updates belong to the next frame
+        synced = true;
+        break;
+      }
+      let reply = match update.cmd {
+        osc::Command::Status => Some(health.message(&stats)),
+        cmd => dmx_universe.execute(update.source, cmd),
+      };
+      if let (Some(reply), source::SourceId::Osc(addr)) = (reply, update.source) {
+        let packet = rosc::encoder::encode(&rosc::OscPacket::Message(reply)).unwrap();
+        if let Err(e) = reply_sock.send_to(&packet, addr) {
+          println!("Error replying to {}: {}", addr, e);
+        }
+      }
+    }
+    let now = std::time::Instant::now();
+    dmx_universe.render(now, &mut frame);
+    if synchronizer.frame(now, synced) {
+      dmx.data[1..].copy_from_slice(&frame);
+    }
+    if let Some(bridge) = &mut bridge {
+      bridge.send(now, &dmx.data[1..]);
+    }
     {
-      let data = data_t.lock().unwrap();
-      dmx.data[1..].copy_from_slice(&*data);
+      let mut snapshot = monitor.lock().unwrap();
+      snapshot.values.clear();
+      snapshot.values.extend_from_slice(dmx_universe.values());
+      snapshot.output.clear();
+      snapshot.output.extend_from_slice(&dmx.data[1..]);
+      snapshot.blackout = dmx_universe.is_blackout();
+      if now >= next_status {
+        next_status = now + std::time::Duration::from_millis(250);
+        snapshot.status = http::status_json(&health.message(&stats));
+      }
+    }
+    for (target, packet) in dmx_universe.feedback(now) {
+      let packet = rosc::encoder::encode(&packet).unwrap();
+      if let Err(e) = reply_sock.send_to(&packet, target) {
+        println!("Error sending feedback to {}: {}", target, e);
+      }
+    }
+    if let Some(target) = heartbeat {
+      if now >= next_heartbeat {
+        next_heartbeat = now + heartbeat_interval;
+        let packet = rosc::encoder::encode(&rosc::OscPacket::Message(health.message(&stats))).unwrap();
+        if let Err(e) = reply_sock.send_to(&packet, target) {
+          println!("Error sending heartbeat to {}: {}", target, e);
+        }
+      }
+    }
+    let result = dmx.wait_and_send();
+    stats.device_open.store(result.is_ok(), std::sync::atomic::Ordering::Relaxed);
+    if health.frame(&result, dmx.data.len()) {
+      match result {
+        Ok(_) => println!("Device is writing again"),
+        Err(e) => println!("Error writing to device: {}", e),
+      }
+    }
+    // one RDM transaction or discovery branch per frame
+    if let Some((port, requests)) = &mut rdm_port {
+      let result = match requests.try_recv() {
+        Ok(request) => port.handle(&mut dmx, request),
+        Err(_) => port.step(&mut dmx),
+      };
+      if let Err(e) = result {
+        println!("RDM error: {}", e);
+      }
     }
-    dmx.wait_and_send().unwrap();
   }
 }
@@ -0,0 +1,62 @@
+use crate::patch::Patch;
+
+// Turns logical universe into the output frame: applies response curves,
+// then routes every logical channel to its output slots.
+pub struct Mapping {
+  curves: Vec<Option<[u8; 256]>>,
+  // (logical, slot) pairs replacing the default one to one routing of the channel
+  routes: Vec<(usize, usize)>,
+  routed: Vec<bool>,
+}
+
+impl Mapping {
+  pub fn new(patch: &Patch, size: usize) -> Mapping {
+    let routes: Vec<(usize, usize)> = patch.routes.iter()
+      .flat_map(|(l, slots)| slots.iter().map(move |s| (*l, *s)))
+      .filter(|(l, s)| *l < size && *s < size)
+      .collect();
+    Mapping {
+      curves: (0..size).map(|c| patch.curves.get(&c).map(|curve| curve.table())).collect(),
+      routed: (0..size).map(|c| routes.iter().any(|(l, _)| *l == c)).collect(),
+      routes,
+    }
+  }
+  fn curve(&self, channel: usize, value: u8) -> u8 {
+    match &self.curves[channel] {
+      Some(t) => t[value as usize],
+      None => value,
+    }
+  }
+  // Slots nothing is routed to stay at zero.
+  pub fn apply(&self, logical: &[u8], frame: &mut [u8]) {
+    for (c, (o, v)) in frame.iter_mut().zip(logical).enumerate() {
+      *o = if self.routed[c] { 0 } else { self.curve(c, *v) };
+    }
+    for &(l, s) in &self.routes {
+      frame[s] = self.curve(l, logical[l]);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn routes_replace_the_default_slot() {
+    let mapping = Mapping::new(&Patch::parse("map 4 8,9\nmap 5 4\ncurve 5 inverse\n").unwrap(), 10);
+    let logical = [0, 1, 2, 3, 40, 50, 6, 7, 80, 90];
+    let mut frame = [0u8; 10];
+    mapping.apply(&logical, &mut frame);
+    // channel 4 moved to 8 and 9, channel 5 to 4, slot 5 is free
+    assert_eq!(frame, [0, 1, 2, 3, 205, 0, 6, 7, 40, 40]);
+  }
+
+  #[test]
+  fn unrouted_channels_go_one_to_one() {
+    let mapping = Mapping::new(&Patch::parse("curve 1 inverse\nmap 20 1\n").unwrap(), 4);
+    let mut frame = [9u8; 4];
+    mapping.apply(&[10, 20, 30, 40], &mut frame);
+    assert_eq!(frame, [10, 235, 30, 40]);
+  }
+}
@@ -0,0 +1,80 @@
+use std::collections::BTreeMap;
+
+use crate::patch::Patch;
+
+struct Submaster {
+  channels: Vec<usize>,
+  level: f32,
+}
+
+// Grand master and submasters, applied to the logical frame, and blackout.
+// Only intensity channels are scaled, blackout holds every output slot at zero but parked ones and limit minimums.
+pub struct Masters {
+  grand: f32,
+  subs: BTreeMap<String, Submaster>,
+  blackout: bool,
+  intensity: Vec<bool>,
+  // per channel product of all levels, recalculated on change
+  scale: Vec<f32>,
+}
+
+impl Masters {
+  pub fn new(patch: &Patch, size: usize) -> Masters {
+    let mut m = Masters {
+      grand: 1_f32,
+      subs: patch.submasters.iter()
+        .map(|(name, channels)| (name.clone(), Submaster { channels: channels.clone(), level: 1_f32 }))
+        .collect(),
+      blackout: false,
+      intensity: (0..size).map(|c| patch.is_intensity(c)).collect(),
+      scale: vec![1_f32; size],
+    };
+    m.recalculate();
+    m
+  }
+  fn recalculate(&mut self) {
+    for (c, s) in self.scale.iter_mut().enumerate() {
+      *s = if self.intensity[c] { self.grand } else { 1_f32 };
+    }
+    for sub in self.subs.values() {
+      for &c in &sub.channels {
+        if c < self.scale.len() && self.intensity[c] {
+          self.scale[c] *= sub.level;
+        }
+      }
+    }
+  }
+  pub fn set_grand(&mut self, level: f32) {
+    self.grand = level;
+    self.recalculate();
+  }
+  pub fn set_sub(&mut self, name: &str, level: f32) -> bool {
+    match self.subs.get_mut(name) {
+      Some(sub) => {
+        sub.level = level;
+        self.recalculate();
+        true
+      },
+      None => false,
+    }
+  }
+  pub fn set_blackout(&mut self, blackout: bool) {
+    self.blackout = blackout;
+  }
+  pub fn is_blackout(&self) -> bool {
+    self.blackout
+  }
+  // Applied to the output frame, after curves which may turn zero into full.
+  pub fn apply_blackout(&self, frame: &mut [u8]) {
+    if self.blackout {
+      frame.fill(0);
+    }
+  }
+  pub fn apply(&self, frame: &mut [u8]) {
+    for (v, s) in frame.iter_mut().zip(&self.scale) {
+      if *s < 1_f32 {
+        *v = (*v as f32 * s).round() as u8;
+      }
+    }
+  }
+}
@@ -0,0 +1,174 @@
+use std::{sync::mpsc::SyncSender, time::{Duration, Instant}};
+
+use rumqttc::{Client, Connection, Event, LastWill, MqttOptions, Packet, QoS, RecvTimeoutError};
+
+use crate::{http::Monitor, ingest::{Ingest, Update}, osc::Command, source::SourceId};
+
+pub const PORT: u16 = 1883;
+const KEEP_ALIVE: Duration = Duration::from_secs(10);
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+// how often changed channels are published
+const STATE_INTERVAL: Duration = Duration::from_millis(100);
+
+// "<host>[:<port>]", IPv6 addresses as "[<address>]:<port>" or without brackets and port
+pub fn parse_broker(s: &str) -> Option<(String, u16)> {
+  if let Some(rest) = s.strip_prefix('[') {
+    let (host, rest) = rest.split_once(']')?;
+    host.parse::<std::net::Ipv6Addr>().ok()?;
+    return match rest.strip_prefix(':') {
+      Some(port) => Some((host.to_string(), port.parse::<u16>().ok()?)),
+      None if rest.is_empty() => Some((host.to_string(), PORT)),
+      None => None,
+    };
+  }
+  match s.rsplit_once(':') {
+    Some(_) if s.matches(':').count() > 1 => Some((s.parse::<std::net::Ipv6Addr>().ok()?.to_string(), PORT)),
+    Some((host, port)) if !host.is_empty() => Some((host.to_string(), port.parse::<u16>().ok()?)),
+    Some(_) => None,
+    None if !s.is_empty() => Some((s.to_string(), PORT)),
+    None => None,
+  }
+}
+
+// Channel and value of a "<topic>/<channel>/set" message with the value as text.
+fn parse_set(topic: &str, size: usize, name: &str, payload: &[u8]) -> Option<(usize, u8)> {
+  let channel = name.strip_prefix(topic)?
+    .strip_prefix('/')?
+    .strip_suffix("/set")?
+    .parse::<usize>().ok()
+    .filter(|c| *c < size)?;
+  let value = std::str::from_utf8(payload).ok()?.trim().parse::<u8>().ok()?;
+  Some((channel, value))
+}
+
+// Bridge of one universe to an MQTT broker: "<prefix>/<universe>/<channel>/set" writes the channel,
+// "<prefix>/<universe>/<channel>/state" is published retained on change,
+// "<prefix>/<universe>/status" is "online" while connected and "offline" as last will.
+pub struct Mqtt {
+  client: Client,
+  connection: Connection,
+  topic: String,
+  size: usize,
+  ingest: Ingest,
+  monitor: Monitor,
+  // last published values, None not published since connecting
+  published: Vec<Option<u8>>,
+  connected: bool,
+  // connection error logged since the last connect
+  reported: bool,
+}
+
+impl Mqtt {
+  pub fn new(id: &str, broker: (String, u16), prefix: &str, universe: i32, size: usize, queue: SyncSender<Update>, monitor: Monitor) -> Mqtt {
+    let topic = format!("{}/{}", prefix, universe);
+    let mut options = MqttOptions::new(id, broker.0, broker.1);
+    options.set_keep_alive(KEEP_ALIVE);
+    options.set_last_will(LastWill::new(format!("{}/status", topic), "offline", QoS::AtLeastOnce, true));
+    // room for every channel changing at once
+    let (client, connection) = Client::new(options, size + 16);
+    Mqtt {
+      client, connection, topic, size, monitor,
+      ingest: Ingest::new(queue, size, 0.0),
+      published: vec![None; size],
+      connected: false,
+      reported: false,
+    }
+  }
+  fn on_connect(&mut self) {
+    println!("Connected to MQTT broker");
+    self.connected = true;
+    self.reported = false;
+    self.published.fill(None);
+    if let Err(e) = self.client.try_subscribe(format!("{}/+/set", self.topic), QoS::AtLeastOnce) {
+      println!("Can't subscribe to MQTT topics: {}", e);
+    }
+    if let Err(e) = self.client.try_publish(format!("{}/status", self.topic), QoS::AtLeastOnce, true, "online") {
+      println!("Can't publish MQTT status: {}", e);
+    }
+  }
+  fn on_publish(&mut self, topic: &str, payload: &[u8]) {
+    match parse_set(&self.topic, self.size, topic, payload) {
+      Some((channel, value)) => {
+        self.ingest.push(SourceId::Mqtt, Command::Set { address: channel, values: vec![Some(value)] });
+      },
+      None => println!("Bad MQTT message on {}: {:?}", topic, String::from_utf8_lossy(payload)),
+    }
+  }
+  fn publish_state(&mut self) {
+    let values = self.monitor.lock().unwrap().values.clone();
+    for (channel, value) in values.iter().enumerate().take(self.size) {
+      if self.published[channel] == Some(*value) {
+        continue;
+      }
+      let topic = format!("{}/{}/state", self.topic, channel);
+      // retried with the next round when the request queue is full
+      if self.client.try_publish(topic, QoS::AtMostOnce, true, value.to_string()).is_ok() {
+        self.published[channel] = Some(*value);
+      }
+    }
+  }
+  pub fn run(&mut self) {
+    let mut next_state = Instant::now();
+    loop {
+      self.ingest.flush();
+      match self.connection.recv_timeout(STATE_INTERVAL) {
+        Ok(Ok(Event::Incoming(Packet::ConnAck(_)))) => self.on_connect(),
+        Ok(Ok(Event::Incoming(Packet::Publish(p)))) => self.on_publish(&p.topic, &p.payload),
+        Ok(Ok(_)) | Err(RecvTimeoutError::Timeout) => {},
+        Ok(Err(e)) => {
+          if !self.reported {
+            println!("MQTT connection error: {}", e);
+            self.reported = true;
+          }
+          self.connected = false;
+          std::thread::sleep(RECONNECT_DELAY);
+        },
+        Err(RecvTimeoutError::Disconnected) => {
+          println!("MQTT client has stopped");
+          std::process::exit(1);
+        },
+      }
+      if self.connected && Instant::now() >= next_state {
+        next_state = Instant::now() + STATE_INTERVAL;
+        self.publish_state();
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn broker_host_and_port() {
+    assert_eq!(parse_broker("localhost"), Some(("localhost".to_string(), PORT)));
+    assert_eq!(parse_broker("10.0.0.1:1884"), Some(("10.0.0.1".to_string(), 1884)));
+    assert_eq!(parse_broker("broker:port"), None);
+    assert_eq!(parse_broker(":1884"), None);
+    assert_eq!(parse_broker(""), None);
+  }
+
+  #[test]
+  fn broker_ipv6() {
+    assert_eq!(parse_broker("::1"), Some(("::1".to_string(), PORT)));
+    assert_eq!(parse_broker("fd00::2"), Some(("fd00::2".to_string(), PORT)));
+    assert_eq!(parse_broker("[::1]"), Some(("::1".to_string(), PORT)));
+    assert_eq!(parse_broker("[fd00::2]:1884"), Some(("fd00::2".to_string(), 1884)));
+    assert_eq!(parse_broker("[::1]1884"), None);
+    assert_eq!(parse_broker("[localhost]:1884"), None);
+    assert_eq!(parse_broker("fd00::2:x"), None);
+  }
+
+  #[test]
+  fn set_topics() {
+    assert_eq!(parse_set("roscdmx/1", 512, "roscdmx/1/0/set", b"255"), Some((0, 255)));
+    assert_eq!(parse_set("roscdmx/1", 512, "roscdmx/1/511/set", b" 7\n"), Some((511, 7)));
+    assert_eq!(parse_set("roscdmx/1", 512, "roscdmx/1/512/set", b"1"), None);
+    assert_eq!(parse_set("roscdmx/1", 512, "roscdmx/10/0/set", b"1"), None);
+    assert_eq!(parse_set("roscdmx/1", 512, "roscdmx/1/0/state", b"1"), None);
+    assert_eq!(parse_set("roscdmx/1", 512, "roscdmx/1/x/set", b"1"), None);
+    assert_eq!(parse_set("roscdmx/1", 512, "roscdmx/1/0/set", b"256"), None);
+    assert_eq!(parse_set("roscdmx/1", 512, "roscdmx/1/0/set", b"\xff"), None);
+  }
+}
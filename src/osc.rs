@@ -0,0 +1,414 @@
+use std::{fmt, error::Error, num::ParseIntError, time::Duration};
+
+use crate::{fade::Profile, effects::{Effect, Waveform, Blend}, scene::Scenes};
+
+#[derive(Debug)]
+pub enum Command {
+  // None keeps the channel as is
+  Set { address: usize, values: Vec<Option<u8>> },
+  Set16 { address: usize, values: Vec<Option<u16>> },
+  Fade { address: usize, values: Vec<Option<u8>>, time: Duration, profile: Profile },
+  Fade16 { address: usize, values: Vec<Option<u16>>, time: Duration, profile: Profile },
+  EffectDefine { name: String, effect: Effect },
+  EffectStart(String),
+  EffectStop(String),
+  EffectRemove(String),
+  SceneRecord(String),
+  SceneRecall { name: String, time: Duration },
+  CueGo,
+  CueBack,
+  CueGoto(f64),
+  CueStop,
+  CueState,
+  GrandMaster(f32),
+  Submaster { name: String, level: f32 },
+  Blackout(bool),
+  Status,
+  Subscribe(u16),
+  Unsubscribe(Option<u16>),
+  // admin namespace
+  Limit { channel: usize, min: u8, max: u8 },
+  Park { channel: usize, value: Option<u8> },
+  Lock { channel: usize, locked: bool },
+  // from network inputs carrying priority per packet, not parsed from OSC
+  Priority(u8),
+  Release,
+  // ArtSync or E1.31 synchronization packet, frame goes out now
+  Sync,
+}
+
+impl Command {
+  pub fn is_admin(&self) -> bool {
+    matches!(self, Command::Limit { .. } | Command::Park { .. } | Command::Lock { .. })
+  }
+}
+
+fn get_shift(starter: &str, addr:&str) -> Result<usize, ParseIntError> {
+  addr[starter.len()..].parse::<usize>()
+}
+
+fn to_u8(arg: &rosc::OscType) -> Option<u8> {
+  match *arg {
+    rosc::OscType::Int(a) => Some(a.clamp(0, 0xff) as u8),
+    rosc::OscType::Long(a) => Some(a.clamp(0, 0xff) as u8),
+    rosc::OscType::Float(f) => Some((f.clamp(0_f32, 1_f32)*255_f32) as u8),
+    rosc::OscType::Double(f) => Some((f.clamp(0_f64, 1_f64)*255_f64) as u8),
+    rosc::OscType::Char(a) => Some(a as u8),
+    rosc::OscType::Bool(b) => Some(b as u8),
+    _ => None,
+  }
+}
+
+fn to_u16(arg: &rosc::OscType) -> Option<u16> {
+  match *arg {
+    rosc::OscType::Int(a) => Some(a.clamp(0, 0xffff) as u16),
+    rosc::OscType::Long(a) => Some(a.clamp(0, 0xffff) as u16),
+    rosc::OscType::Float(f) => Some((f.clamp(0_f32, 1_f32)*65535_f32) as u16),
+    rosc::OscType::Double(f) => Some((f.clamp(0_f64, 1_f64)*65535_f64) as u16),
+    _ => None,
+  }
+}
+
+fn to_f32(arg: &rosc::OscType) -> Option<f32> {
+  match *arg {
+    rosc::OscType::Int(a) => Some(a as f32),
+    rosc::OscType::Long(a) => Some(a as f32),
+    rosc::OscType::Float(f) if f.is_finite() => Some(f),
+    rosc::OscType::Double(f) if f.is_finite() => Some(f as f32),
+    _ => None,
+  }
+}
+
+fn to_i32(arg: &rosc::OscType) -> Option<i32> {
+  match *arg {
+    rosc::OscType::Int(a) => Some(a),
+    rosc::OscType::Long(a) => i32::try_from(a).ok(),
+    _ => None,
+  }
+}
+
+// 0..1 for floats, 0..255 for integers
+fn to_level(arg: &rosc::OscType) -> Option<f32> {
+  match *arg {
+    rosc::OscType::Int(_) | rosc::OscType::Long(_) => to_u8(arg).map(|v| v as f32 / 255_f32),
+    rosc::OscType::Float(_) | rosc::OscType::Double(_) => to_f32(arg).map(|v| v.clamp(0_f32, 1_f32)),
+    _ => None,
+  }
+}
+
+fn to_seconds(arg: &rosc::OscType) -> Option<Duration> {
+  let s = match *arg {
+    rosc::OscType::Int(a) => a as f64,
+    rosc::OscType::Long(a) => a as f64,
+    rosc::OscType::Float(f) => f as f64,
+    rosc::OscType::Double(f) => f,
+    _ => return None,
+  };
+  // negative, NaN and too long times are rejected
+  Duration::try_from_secs_f64(s).ok()
+}
+
+// <value...> <seconds> [<profile>]
+fn fade_args(args: &[rosc::OscType]) -> Option<(&[rosc::OscType], Duration, Profile)> {
+  let (args, profile) = match args.last() {
+    Some(rosc::OscType::String(p)) => (&args[..args.len()-1], Profile::from_name(p)?),
+    _ => (args, Profile::Linear),
+  };
+  let (time, values) = args.split_last()?;
+  Some((values, to_seconds(time)?, profile))
+}
+
+// <waveform> [<blend>] <rate> <spread> <min> <max> <channel...>
+fn effect_args(args: &[rosc::OscType]) -> Option<Effect> {
+  let mut args = args.iter();
+  let waveform = match args.next()? {
+    rosc::OscType::String(w) => Waveform::from_name(w)?,
+    _ => return None,
+  };
+  let mut arg = args.next()?;
+  let blend = match arg {
+    rosc::OscType::String(b) => {
+      arg = args.next()?;
+      Blend::from_name(b)?
+    },
+    _ => Blend::Htp,
+  };
+  let rate = to_f32(arg)?;
+  let spread = to_f32(args.next()?)?;
+  let min = to_i32(args.next()?)?;
+  let max = to_i32(args.next()?)?;
+  let channels = args.map(|a| to_i32(a).and_then(|c| usize::try_from(c).ok()))
+    .collect::<Option<Vec<usize>>>()?;
+  if channels.is_empty() {
+    return None;
+  }
+  Some(Effect::new(waveform, blend, rate, spread, min, max, channels))
+}
+
+fn scene_name(arg: Option<&rosc::OscType>) -> Option<String> {
+  match arg {
+    Some(rosc::OscType::String(n)) if Scenes::valid_name(n) => Some(n.clone()),
+    _ => None,
+  }
+}
+
+fn to_channel(arg: Option<&rosc::OscType>) -> Option<usize> {
+  to_i32(arg?).and_then(|c| usize::try_from(c).ok())
+}
+
+fn to_port(arg: &rosc::OscType) -> Option<u16> {
+  u16::try_from(to_i32(arg)?).ok()
+}
+
+fn to_switch(arg: &rosc::OscType) -> Option<bool> {
+  match arg {
+    rosc::OscType::String(s) => match s.as_str() {
+      "on" => Some(true),
+      "off" => Some(false),
+      _ => None,
+    },
+    rosc::OscType::Bool(b) => Some(*b),
+    a => Some(to_i32(a)? != 0),
+  }
+}
+
+fn to_cue_number(arg: &rosc::OscType) -> Option<f64> {
+  match arg {
+    rosc::OscType::String(n) => n.parse::<f64>().ok().filter(|n| n.is_finite()),
+    rosc::OscType::Double(n) if n.is_finite() => Some(*n),
+    a => Some(to_f32(a)? as f64),
+  }
+}
+
+fn all<T>(args: &[rosc::OscType], f: fn(&rosc::OscType) -> Option<T>) -> Result<Vec<Option<T>>, ParseError> {
+  if args.is_empty() {
+    return Err(ParseError::BadArguments);
+  }
+  args.iter().map(|a| f(a).map(Some).ok_or(ParseError::BadArguments)).collect()
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ParseError {
+  Decode,
+  UnknownAddress,
+  BadAddress,
+  BadArguments,
+  OutOfRange,
+}
+impl fmt::Display for ParseError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ParseError::Decode => write!(f, "decode error"),
+      ParseError::UnknownAddress => write!(f, "unknown address"),
+      ParseError::BadAddress => write!(f, "bad address"),
+      ParseError::BadArguments => write!(f, "bad arguments"),
+      ParseError::OutOfRange => write!(f, "out of range"),
+    }
+  }
+}
+impl Error for ParseError {}
+
+// Messages of the packet, bundles are flattened.
+pub fn messages(packet: rosc::OscPacket) -> Vec<rosc::OscMessage> {
+  match packet {
+    rosc::OscPacket::Message(msg) => vec![msg],
+    rosc::OscPacket::Bundle(b) => b.content.into_iter().flat_map(messages).collect(),
+  }
+}
+
+pub struct Parser {
+  // "/<universe_number>/"
+  prefix: String,
+  size: usize,
+}
+
+impl Parser {
+  pub fn new(prefix: &str, size: usize) -> Parser {
+    Parser { prefix: prefix.to_string(), size }
+  }
+  fn check_range(&self, first: usize, count: usize) -> Result<(), ParseError> {
+    match first.checked_add(count) {
+      Some(end) if end <= self.size => Ok(()),
+      _ => Err(ParseError::OutOfRange),
+    }
+  }
+  fn parse_admin(&self, method: &str, args: &[rosc::OscType]) -> Option<Command> {
+    let channel = to_channel(args.first())?;
+    match method {
+      "limit" => Some(Command::Limit { channel, min: to_u8(args.get(1)?)?, max: to_u8(args.get(2)?)? }),
+      "park" => Some(Command::Park { channel, value: Some(to_u8(args.get(1)?)?) }),
+      "unpark" => Some(Command::Park { channel, value: None }),
+      "lock" => Some(Command::Lock { channel, locked: true }),
+      "unlock" => Some(Command::Lock { channel, locked: false }),
+      _ => None,
+    }
+  }
+  pub fn parse(&self, msg: &rosc::OscMessage) -> Result<Command, ParseError> {
+    let args = &msg.args;
+    let bad = || ParseError::BadArguments;
+    let cmd = match msg.addr.as_str() {
+      "/scene/record" => Command::SceneRecord(scene_name(args.first()).ok_or_else(bad)?),
+      "/scene/recall" => {
+        let time = match args.get(1) {
+          Some(t) => to_seconds(t).ok_or_else(bad)?,
+          None => Duration::ZERO,
+        };
+        Command::SceneRecall { name: scene_name(args.first()).ok_or_else(bad)?, time }
+      },
+      "/roscdmx/status" => Command::Status,
+      "/subscribe" => Command::Subscribe(args.first().and_then(to_port).ok_or_else(bad)?),
+      "/unsubscribe" => match args.first() {
+        Some(p) => Command::Unsubscribe(Some(to_port(p).ok_or_else(bad)?)),
+        None => Command::Unsubscribe(None),
+      },
+      "/cue/go" => Command::CueGo,
+      "/cue/back" => Command::CueBack,
+      "/cue/stop" => Command::CueStop,
+      "/cue/state" => Command::CueState,
+      "/cue/goto" => Command::CueGoto(args.first().and_then(to_cue_number).ok_or_else(bad)?),
+      "/master" => Command::GrandMaster(args.first().and_then(to_level).ok_or_else(bad)?),
+      "/blackout" => Command::Blackout(args.first().and_then(to_switch).ok_or_else(bad)?),
+      addr => return self.parse_namespaced(addr, args),
+    };
+    Ok(cmd)
+  }
+  fn parse_namespaced(&self, addr: &str, args: &[rosc::OscType]) -> Result<Command, ParseError> {
+    let bad = || ParseError::BadArguments;
+    if let Some(method) = addr.strip_prefix("/roscdmx/admin/") {
+      let cmd = self.parse_admin(method, args).ok_or_else(bad)?;
+      match cmd {
+        Command::Limit { channel, .. } | Command::Park { channel, .. } | Command::Lock { channel, .. } => {
+          self.check_range(channel, 1)?;
+        },
+        _ => {},
+      }
+      return Ok(cmd);
+    }
+    if let Some(name) = addr.strip_prefix("/sub/") {
+      return Ok(Command::Submaster { name: name.to_string(), level: args.first().and_then(to_level).ok_or_else(bad)? });
+    }
+    let method = match addr.strip_prefix(&self.prefix) {
+      Some(m) => m,
+      None => return Err(ParseError::UnknownAddress),
+    };
+    let address = |starter: &str| get_shift(starter, method).map_err(|_| ParseError::BadAddress);
+    if method.starts_with("dmx/") {
+      let address = address("dmx/")?;
+      let values = all(args, to_u8)?;
+      self.check_range(address, values.len())?;
+      Ok(Command::Set { address, values })
+    } else if method.starts_with("dmx16/") {
+      let address = address("dmx16/")?;
+      let values = all(args, to_u16)?;
+      self.check_range(address, 2*values.len())?;
+      Ok(Command::Set16 { address, values })
+    } else if method.starts_with("fade/") {
+      let address = address("fade/")?;
+      let (values, time, profile) = fade_args(args).ok_or_else(bad)?;
+      let values = all(values, to_u8)?;
+      self.check_range(address, values.len())?;
+      Ok(Command::Fade { address, values, time, profile })
+    } else if method.starts_with("fade16/") {
+      let address = address("fade16/")?;
+      let (values, time, profile) = fade_args(args).ok_or_else(bad)?;
+      let values = all(values, to_u16)?;
+      self.check_range(address, 2*values.len())?;
+      Ok(Command::Fade16 { address, values, time, profile })
+    } else if let Some(name) = method.strip_prefix("fx/") {
+      if let Some(name) = name.strip_suffix("/start") {
+        Ok(Command::EffectStart(name.to_string()))
+      } else if let Some(name) = name.strip_suffix("/stop") {
+        Ok(Command::EffectStop(name.to_string()))
+      } else if let Some(name) = name.strip_suffix("/remove") {
+        Ok(Command::EffectRemove(name.to_string()))
+      } else if !name.is_empty() && !name.contains('/') {
+        let effect = effect_args(args).ok_or_else(bad)?;
+        for c in &effect.channels {
+          self.check_range(*c, 1)?;
+        }
+        Ok(Command::EffectDefine { name: name.to_string(), effect })
+      } else {
+        Err(ParseError::BadAddress)
+      }
+    } else {
+      Err(ParseError::UnknownAddress)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rosc::OscType::{Float, Int, String as Str};
+
+  fn parse(addr: &str, args: Vec<rosc::OscType>) -> Result<Command, ParseError> {
+    Parser::new("/0/", 512).parse(&rosc::OscMessage { addr: addr.to_string(), args })
+  }
+
+  #[test]
+  fn channel_ranges() {
+    assert!(matches!(parse("/0/dmx/511", vec![Int(1)]), Ok(Command::Set { address: 511, .. })));
+    // used to underflow on dmx_size - shift
+    assert!(matches!(parse("/0/dmx/511", vec![Int(1), Int(2)]), Err(ParseError::OutOfRange)));
+    assert!(matches!(parse("/0/dmx/512", vec![Int(1)]), Err(ParseError::OutOfRange)));
+    assert!(matches!(parse(&format!("/0/dmx/{}", usize::MAX), vec![Int(1)]), Err(ParseError::OutOfRange)));
+    assert!(matches!(parse("/0/fade/511", vec![Int(1), Int(2), Int(1)]), Err(ParseError::OutOfRange)));
+    assert!(matches!(parse("/roscdmx/admin/park", vec![Int(512), Int(1)]), Err(ParseError::OutOfRange)));
+  }
+
+  #[test]
+  fn dmx16_ranges() {
+    match parse("/0/dmx16/510", vec![Int(0x1234)]) {
+      Ok(Command::Set16 { address: 510, values }) => assert_eq!(values, vec![Some(0x1234)]),
+      c => panic!("unexpected {:?}", c),
+    }
+    assert!(matches!(parse("/0/dmx16/511", vec![Int(1)]), Err(ParseError::OutOfRange)));
+    assert!(matches!(parse("/0/dmx16/508", vec![Int(1), Int(2)]), Ok(Command::Set16 { .. })));
+    assert!(matches!(parse("/0/dmx16/509", vec![Int(1), Int(2)]), Err(ParseError::OutOfRange)));
+    assert!(matches!(parse("/0/fade16/510", vec![Int(1), Int(2), Int(1)]), Err(ParseError::OutOfRange)));
+  }
+
+  #[test]
+  fn bad_arguments() {
+    assert!(matches!(parse("/0/dmx/0", vec![]), Err(ParseError::BadArguments)));
+    assert!(matches!(parse("/0/dmx/0", vec![Str("full".to_string())]), Err(ParseError::BadArguments)));
+    assert!(matches!(parse("/0/dmx/0", vec![Int(1), rosc::OscType::Blob(vec![1])]), Err(ParseError::BadArguments)));
+    assert!(matches!(parse("/0/dmx16/0", vec![rosc::OscType::Char('a')]), Err(ParseError::BadArguments)));
+    assert!(matches!(parse("/master", vec![rosc::OscType::Nil]), Err(ParseError::BadArguments)));
+    assert!(matches!(parse("/subscribe", vec![Int(70000)]), Err(ParseError::BadArguments)));
+    assert!(matches!(parse("/scene/record", vec![Str("no spaces".to_string())]), Err(ParseError::BadArguments)));
+    // values are clamped, not rejected
+    match parse("/0/dmx/0", vec![Int(300), Float(0.5), Int(-1)]) {
+      Ok(Command::Set { values, .. }) => assert_eq!(values, vec![Some(255), Some(127), Some(0)]),
+      c => panic!("unexpected {:?}", c),
+    }
+  }
+
+  #[test]
+  fn bad_addresses() {
+    assert!(matches!(parse("/1/dmx/0", vec![Int(1)]), Err(ParseError::UnknownAddress)));
+    assert!(matches!(parse("/0/nothing/0", vec![Int(1)]), Err(ParseError::UnknownAddress)));
+    assert!(matches!(parse("/0/fx/a/b", vec![]), Err(ParseError::BadAddress)));
+    assert!(matches!(parse("", vec![]), Err(ParseError::UnknownAddress)));
+    assert!(matches!(parse("/0/dmx/x", vec![Int(1)]), Err(ParseError::BadAddress)));
+    assert!(matches!(parse("/0/dmx/-1", vec![Int(1)]), Err(ParseError::BadAddress)));
+    assert!(matches!(parse("/0/dmx/", vec![Int(1)]), Err(ParseError::BadAddress)));
+    assert!(matches!(parse("/0/dmx16/99999999999999999999999", vec![Int(1)]), Err(ParseError::BadAddress)));
+  }
+
+  #[test]
+  fn fade_times() {
+    match parse("/0/fade/10", vec![Int(255), Int(0), Float(2.5), Str("scurve".to_string())]) {
+      Ok(Command::Fade { address: 10, values, time, profile: Profile::SCurve }) => {
+        assert_eq!(values, vec![Some(255), Some(0)]);
+        assert_eq!(time, Duration::from_millis(2500));
+      },
+      c => panic!("unexpected {:?}", c),
+    }
+    // too long for a Duration, negative or NaN
+    for time in [1e20_f64, -1_f64, f64::NAN] {
+      assert!(matches!(parse("/0/fade/0", vec![Int(255), rosc::OscType::Double(time)]), Err(ParseError::BadArguments)));
+      assert!(matches!(parse("/scene/recall", vec![Str("x".to_string()), rosc::OscType::Double(time)]), Err(ParseError::BadArguments)));
+    }
+  }
+}
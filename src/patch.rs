@@ -0,0 +1,335 @@
+use std::{fmt, error::Error, collections::{HashMap, BTreeMap, BTreeSet}};
+
+#[derive(Debug)]
+pub enum PatchError {
+  IOError(std::io::Error),
+  Syntax(usize, String),
+}
+impl fmt::Display for PatchError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      PatchError::IOError(e) => write!(f, "Can't read patch: {}", e),
+      PatchError::Syntax(line, e) => write!(f, "Patch line {}: {}", line, e),
+    }
+  }
+}
+impl Error for PatchError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      PatchError::IOError(e) => Some(e),
+      PatchError::Syntax(_, _) => None,
+    }
+  }
+}
+impl From<std::io::Error> for PatchError {
+  fn from(e: std::io::Error) -> Self {
+    PatchError::IOError(e)
+  }
+}
+
+// 16 bit attribute: MSB goes to coarse channel, LSB to fine one.
+// LSB-first fixtures simply have fine channel before the coarse one.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Attribute16 {
+  pub coarse: usize,
+  pub fine: usize,
+}
+impl Attribute16 {
+  pub fn write(&self, data: &mut [u8], value: u16) {
+    if self.coarse < data.len() {
+      data[self.coarse] = (value >> 8) as u8;
+    }
+    if self.fine < data.len() {
+      data[self.fine] = (value & 0xff) as u8;
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Curve {
+  Linear,
+  Square,
+  Inverse,
+  Gamma(f32),
+  Table(Vec<u8>),
+}
+impl Curve {
+  pub fn table(&self) -> [u8; 256] {
+    let mut t = [0u8; 256];
+    for (i, v) in t.iter_mut().enumerate() {
+      let x = i as f32 / 255_f32;
+      *v = match self {
+        Curve::Linear => i as u8,
+        Curve::Square => (x * x * 255_f32).round() as u8,
+        Curve::Inverse => 255 - i as u8,
+        Curve::Gamma(g) => (x.powf(*g) * 255_f32).round() as u8,
+        Curve::Table(table) => table[i],
+      };
+    }
+    t
+  }
+}
+
+#[derive(Debug, Default)]
+pub struct Patch {
+  // keyed by the lowest channel of the attribute, which is the one used in OSC addresses
+  attributes16: HashMap<usize, Attribute16>,
+  intensity: BTreeSet<usize>,
+  // true for HTP, overrides intensity based default
+  merge: BTreeMap<usize, bool>,
+  pub submasters: BTreeMap<String, Vec<usize>>,
+  // logical channel -> output slots
+  pub routes: BTreeMap<usize, Vec<usize>>,
+  pub curves: BTreeMap<usize, Curve>,
+  pub limits: BTreeMap<usize, (u8, u8)>,
+  pub parked: BTreeMap<usize, u8>,
+  pub locked: BTreeSet<usize>,
+}
+
+fn parse_address(line: usize, s: Option<&str>) -> Result<usize, PatchError> {
+  match s {
+    Some(s) => s.parse::<usize>()
+      .map_err(|e| PatchError::Syntax(line, format!("bad address {:?}: {}", s, e))),
+    None => Err(PatchError::Syntax(line, "address expected".to_string())),
+  }
+}
+
+// "1-8,12 14" -> [1, 2, ..., 8, 12, 14]
+fn parse_channels<'a>(line: usize, words: impl Iterator<Item = &'a str>) -> Result<Vec<usize>, PatchError> {
+  let mut channels = Vec::new();
+  for part in words.flat_map(|w| w.split(',')).filter(|p| !p.is_empty()) {
+    match part.split_once('-') {
+      Some((from, to)) => {
+        let from = parse_address(line, Some(from))?;
+        let to = parse_address(line, Some(to))?;
+        if to < from {
+          return Err(PatchError::Syntax(line, format!("bad range {:?}", part)));
+        }
+        channels.extend(from..=to);
+      },
+      None => channels.push(parse_address(line, Some(part))?),
+    }
+  }
+  if channels.is_empty() {
+    return Err(PatchError::Syntax(line, "channel list expected".to_string()));
+  }
+  Ok(channels)
+}
+
+fn parse_curve<'a>(line: usize, words: &mut impl Iterator<Item = &'a str>) -> Result<Curve, PatchError> {
+  match words.next() {
+    Some("linear") => Ok(Curve::Linear),
+    Some("square") => Ok(Curve::Square),
+    Some("inverse") => Ok(Curve::Inverse),
+    Some("gamma") => match words.next().map(|g| g.parse::<f32>()) {
+      Some(Ok(g)) if g.is_finite() && g > 0_f32 => Ok(Curve::Gamma(g)),
+      _ => Err(PatchError::Syntax(line, "gamma value expected".to_string())),
+    },
+    Some("lut") => {
+      let table = words.flat_map(|w| w.split(',')).filter(|v| !v.is_empty())
+        .map(|v| v.parse::<u8>().map_err(|e| PatchError::Syntax(line, format!("bad table value {:?}: {}", v, e))))
+        .collect::<Result<Vec<u8>, PatchError>>()?;
+      if table.len() != 256 {
+        return Err(PatchError::Syntax(line, format!("table has {} values instead of 256", table.len())));
+      }
+      Ok(Curve::Table(table))
+    },
+    Some(c) => Err(PatchError::Syntax(line, format!("unknown curve {:?}", c))),
+    None => Err(PatchError::Syntax(line, "curve expected".to_string())),
+  }
+}
+
+fn parse_value(line: usize, s: Option<&str>) -> Result<u8, PatchError> {
+  match s {
+    Some(s) => s.parse::<u8>()
+      .map_err(|e| PatchError::Syntax(line, format!("bad value {:?}: {}", s, e))),
+    None => Err(PatchError::Syntax(line, "value expected".to_string())),
+  }
+}
+
+fn parse_address_list(line: usize, s: Option<&str>) -> Result<Vec<usize>, PatchError> {
+  match s {
+    Some(s) => parse_channels(line, std::iter::once(s)),
+    None => Err(PatchError::Syntax(line, "channel list expected".to_string())),
+  }
+}
+
+impl Patch {
+  pub fn new() -> Patch {
+    Patch::default()
+  }
+  pub fn load(path: &str) -> Result<Patch, PatchError> {
+    Patch::parse(&std::fs::read_to_string(path)?)
+  }
+  pub fn parse(text: &str) -> Result<Patch, PatchError> {
+    let mut patch = Patch::new();
+    for (i, line) in text.lines().enumerate() {
+      let n = i + 1;
+      let line = line.split('#').next().unwrap_or("");
+      let mut words = line.split_whitespace();
+      match words.next() {
+        None => continue,
+        Some("16bit") => {
+          let coarse = parse_address(n, words.next())?;
+          let fine = match words.next() {
+            Some(s) => parse_address(n, Some(s))?,
+            None => coarse + 1,
+          };
+          if coarse == fine {
+            return Err(PatchError::Syntax(n, "coarse and fine channels are the same".to_string()));
+          }
+          patch.attributes16.insert(coarse.min(fine), Attribute16 { coarse, fine });
+        },
+        Some("intensity") => {
+          patch.intensity.extend(parse_channels(n, &mut words)?);
+        },
+        Some("htp") => {
+          patch.merge.extend(parse_channels(n, &mut words)?.into_iter().map(|c| (c, true)));
+        },
+        Some("ltp") => {
+          patch.merge.extend(parse_channels(n, &mut words)?.into_iter().map(|c| (c, false)));
+        },
+        Some("map") => {
+          let logical = parse_address(n, words.next())?;
+          let slots = parse_channels(n, &mut words)?;
+          patch.routes.entry(logical).or_default().extend(slots);
+        },
+        Some("curve") => {
+          let channels = parse_address_list(n, words.next())?;
+          let curve = parse_curve(n, &mut words)?;
+          for c in channels {
+            patch.curves.insert(c, curve.clone());
+          }
+        },
+        Some("limit") => {
+          let channels = parse_address_list(n, words.next())?;
+          let min = parse_value(n, words.next())?;
+          let max = parse_value(n, words.next())?;
+          if min > max {
+            return Err(PatchError::Syntax(n, "minimum is above maximum".to_string()));
+          }
+          patch.limits.extend(channels.into_iter().map(|c| (c, (min, max))));
+        },
+        Some("park") => {
+          let channels = parse_address_list(n, words.next())?;
+          let value = parse_value(n, words.next())?;
+          patch.parked.extend(channels.into_iter().map(|c| (c, value)));
+        },
+        Some("lock") => {
+          patch.locked.extend(parse_channels(n, &mut words)?);
+        },
+        Some("sub") => {
+          let name = match words.next() {
+            Some(name) => name.to_string(),
+            None => return Err(PatchError::Syntax(n, "submaster name expected".to_string())),
+          };
+          let channels = parse_channels(n, &mut words)?;
+          patch.submasters.entry(name).or_default().extend(channels);
+        },
+        Some(w) => return Err(PatchError::Syntax(n, format!("unknown directive {:?}", w))),
+      }
+      if let Some(w) = words.next() {
+        return Err(PatchError::Syntax(n, format!("unexpected {:?}", w)));
+      }
+    }
+    Ok(patch)
+  }
+  // Declared attribute at address, or MSB-first pair starting there.
+  pub fn attribute16(&self, address: usize) -> Attribute16 {
+    match self.attributes16.get(&address) {
+      Some(a) => *a,
+      None => Attribute16 { coarse: address, fine: address + 1 },
+    }
+  }
+  // Without any intensity declaration every channel is scaled by masters.
+  pub fn is_intensity(&self, channel: usize) -> bool {
+    self.intensity.is_empty() || self.intensity.contains(&channel)
+  }
+  // Intensity channels are merged HTP, others LTP, unless declared otherwise.
+  pub fn is_htp(&self, channel: usize) -> bool {
+    self.merge.get(&channel).copied().unwrap_or_else(|| self.is_intensity(channel))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn syntax_line(text: &str) -> Option<usize> {
+    match Patch::parse(text) {
+      Err(PatchError::Syntax(line, _)) => Some(line),
+      _ => None,
+    }
+  }
+
+  #[test]
+  fn attributes16() {
+    let patch = Patch::parse("# pan and tilt\n16bit 10\n16bit 21 20  # LSB first\n").unwrap();
+    assert_eq!(patch.attribute16(10), Attribute16 { coarse: 10, fine: 11 });
+    assert_eq!(patch.attribute16(20), Attribute16 { coarse: 21, fine: 20 });
+    // undeclared pairs are MSB first
+    assert_eq!(patch.attribute16(30), Attribute16 { coarse: 30, fine: 31 });
+    let mut data = [0u8; 32];
+    patch.attribute16(20).write(&mut data, 0x1234);
+    assert_eq!((data[21], data[20]), (0x12, 0x34));
+  }
+
+  #[test]
+  fn malformed() {
+    assert_eq!(syntax_line("16bit 1\n16bit 2 2\n"), Some(2));
+    assert_eq!(syntax_line("16bit\n"), Some(1));
+    assert_eq!(syntax_line("16bit x\n"), Some(1));
+    assert_eq!(syntax_line("16bit 1 2 3\n"), Some(1));
+    assert_eq!(syntax_line("\n\nfrobnicate 1\n"), Some(3));
+  }
+
+  #[test]
+  fn intensity_and_submasters() {
+    let patch = Patch::parse("intensity 1-3,5 7\nsub front 1-2\nsub front 5\n").unwrap();
+    assert!(patch.is_intensity(1) && patch.is_intensity(3) && patch.is_intensity(7));
+    assert!(!patch.is_intensity(4));
+    assert_eq!(patch.submasters["front"], vec![1, 2, 5]);
+    // without declaration everything is intensity
+    assert!(Patch::parse("").unwrap().is_intensity(100));
+    assert_eq!(syntax_line("intensity 5-3\n"), Some(1));
+    assert_eq!(syntax_line("intensity\n"), Some(1));
+    assert_eq!(syntax_line("sub\n"), Some(1));
+    assert_eq!(syntax_line("sub front\n"), Some(1));
+  }
+
+
+  #[test]
+  fn merge_overrides() {
+    let patch = Patch::parse("intensity 1-3\nltp 2\nhtp 8-9\n").unwrap();
+    assert!(patch.is_htp(1) && !patch.is_htp(2) && !patch.is_htp(4) && patch.is_htp(9));
+    assert_eq!(syntax_line("htp x\n"), Some(1));
+  }
+
+
+  #[test]
+  fn routes_and_curves() {
+    let lut: Vec<String> = (0..256).map(|v| (255 - v).to_string()).collect();
+    let patch = Patch::parse(&format!("map 4 8,9\nmap 4 10\ncurve 1,2 gamma 2.2\ncurve 3 lut {}\n", lut.join(","))).unwrap();
+    assert_eq!(patch.routes[&4], vec![8, 9, 10]);
+    assert_eq!(patch.curves[&2], Curve::Gamma(2.2));
+    assert_eq!(patch.curves[&3].table(), Curve::Inverse.table());
+    assert_eq!(Curve::Square.table()[128], 64);
+    assert_eq!(syntax_line("map 4\n"), Some(1));
+    assert_eq!(syntax_line("curve 1 gamma -1\n"), Some(1));
+    assert_eq!(syntax_line("curve 1 lut 1,2,3\n"), Some(1));
+    assert_eq!(syntax_line("curve 1 cubic\n"), Some(1));
+  }
+
+
+  #[test]
+  fn limits_parks_and_locks() {
+    let patch = Patch::parse("limit 4-5 10 200\npark 6 255\nlock 7,8\n").unwrap();
+    assert_eq!(patch.limits[&5], (10, 200));
+    assert_eq!(patch.parked[&6], 255);
+    assert!(patch.locked.contains(&8));
+    assert_eq!(syntax_line("limit 1 200 10\n"), Some(1));
+    assert_eq!(syntax_line("limit 1 10\n"), Some(1));
+    assert_eq!(syntax_line("park 1 256\n"), Some(1));
+    assert_eq!(syntax_line("lock\n"), Some(1));
+  }
+}
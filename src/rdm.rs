@@ -0,0 +1,348 @@
+use std::{collections::BTreeSet, fmt};
+
+use crate::ftd2xx::FTError;
+
+pub const START_CODE: u8 = 0xcc;
+const SUB_START_CODE: u8 = 0x01;
+const DISCOVERY_COMMAND: u8 = 0x10;
+const GET_COMMAND: u8 = 0x20;
+const DISC_UNIQUE_BRANCH: u16 = 0x0001;
+const DISC_MUTE: u16 = 0x0002;
+const DISC_UN_MUTE: u16 = 0x0003;
+// prototype range of ESTA manufacturer IDs
+const MANUFACTURER_ID: u16 = 0x7ff0;
+// limits time spent on a bus full of collisions
+const MAX_BRANCHES: usize = 4096;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Uid(pub [u8; 6]);
+impl Uid {
+  pub const BROADCAST: Uid = Uid([0xff; 6]);
+  fn from_u64(v: u64) -> Uid {
+    let b = v.to_be_bytes();
+    Uid([b[2], b[3], b[4], b[5], b[6], b[7]])
+  }
+  fn to_u64(self) -> u64 {
+    let b = self.0;
+    u64::from_be_bytes([0, 0, b[0], b[1], b[2], b[3], b[4], b[5]])
+  }
+  // Controller UID of this node, from the device serial.
+  pub fn controller(serial: &str) -> Uid {
+    // FNV-1a
+    let hash = serial.bytes().fold(0x811c9dc5_u32, |h, b| (h ^ b as u32).wrapping_mul(0x01000193));
+    Uid::from_u64((MANUFACTURER_ID as u64) << 32 | hash as u64)
+  }
+  fn is_broadcast(&self) -> bool {
+    self.0[2..] == [0xff; 4]
+  }
+}
+impl fmt::Display for Uid {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let b = self.0;
+    write!(f, "{:02x}{:02x}:{:02x}{:02x}{:02x}{:02x}", b[0], b[1], b[2], b[3], b[4], b[5])
+  }
+}
+
+fn checksum(data: &[u8]) -> u16 {
+  data.iter().fold(0_u16, |s, b| s.wrapping_add(*b as u16))
+}
+
+// RDM line, sends a packet after break and mark after break.
+pub trait Bus {
+  // Returns everything received after the packet until the line went quiet,
+  // empty if nothing is expected or nothing arrived in time.
+  fn transaction(&mut self, packet: &[u8], response: bool) -> Result<Vec<u8>, FTError>;
+}
+
+// Request packet with the start code.
+pub fn encode(dest: Uid, src: Uid, transaction: u8, command: u8, pid: u16, data: &[u8]) -> Vec<u8> {
+  let mut p = vec![START_CODE, SUB_START_CODE, (24 + data.len()) as u8];
+  p.extend_from_slice(&dest.0);
+  p.extend_from_slice(&src.0);
+  // transaction number, port ID, message count, sub-device
+  p.extend_from_slice(&[transaction, 1, 0, 0, 0, command]);
+  p.extend_from_slice(&pid.to_be_bytes());
+  p.push(data.len() as u8);
+  p.extend_from_slice(data);
+  let sum = checksum(&p);
+  p.extend_from_slice(&sum.to_be_bytes());
+  p
+}
+
+// True for GET requests, which don't change anything on the responder. Packet is without the start code.
+pub fn is_get(packet: &[u8]) -> bool {
+  packet.get(19) == Some(&GET_COMMAND)
+}
+
+// Finds a valid response in the received bytes, returned without the start code.
+pub fn decode(received: &[u8]) -> Option<&[u8]> {
+  let start = received.windows(2).position(|w| w == [START_CODE, SUB_START_CODE])?;
+  let p = &received[start..];
+  let length = *p.get(2)? as usize;
+  if length < 24 || p.len() < length + 2 {
+    return None;
+  }
+  if checksum(&p[..length]) != u16::from_be_bytes([p[length], p[length+1]]) {
+    return None;
+  }
+  Some(&p[1..length+2])
+}
+
+// Decodes DISC_UNIQUE_BRANCH response: preamble, 0xaa, UID and checksum with every byte sent twice.
+fn decode_discovery(received: &[u8]) -> Option<Uid> {
+  let start = received.iter().position(|b| *b == 0xaa)? + 1;
+  let e = received.get(start..start+16)?;
+  let mut uid = [0u8; 6];
+  for i in 0..6 {
+    uid[i] = e[2*i] & e[2*i+1];
+  }
+  let sum = u16::from_be_bytes([e[12] & e[13], e[14] & e[15]]);
+  if checksum(&e[..12]) != sum {
+    return None;
+  }
+  Some(Uid(uid))
+}
+
+// Discovers responders and forwards requests of remote controllers.
+// Discovery runs one branch per call of `step`, so DMX output keeps going in between.
+pub struct Controller {
+  uid: Uid,
+  transaction: u8,
+  tod: BTreeSet<Uid>,
+  // ranges of UIDs still to search
+  branches: Vec<(u64, u64)>,
+  searched: usize,
+}
+
+impl Controller {
+  pub fn new(uid: Uid) -> Controller {
+    Controller { uid, transaction: 0, tod: BTreeSet::new(), branches: Vec::new(), searched: 0 }
+  }
+  pub fn tod(&self) -> Vec<Uid> {
+    self.tod.iter().copied().collect()
+  }
+  pub fn is_discovering(&self) -> bool {
+    !self.branches.is_empty()
+  }
+  fn send(&mut self, bus: &mut dyn Bus, dest: Uid, pid: u16, data: &[u8]) -> Result<Vec<u8>, FTError> {
+    self.transaction = self.transaction.wrapping_add(1);
+    let packet = encode(dest, self.uid, self.transaction, DISCOVERY_COMMAND, pid, data);
+    // unique branch is broadcast, but every responder in the range answers it
+    bus.transaction(&packet, pid == DISC_UNIQUE_BRANCH || !dest.is_broadcast())
+  }
+  fn mute(&mut self, bus: &mut dyn Bus, uid: Uid) -> Result<bool, FTError> {
+    let response = self.send(bus, uid, DISC_MUTE, &[])?;
+    Ok(decode(&response).is_some())
+  }
+  // Full discovery forgets known responders, incremental one keeps those still answering.
+  pub fn start_discovery(&mut self, bus: &mut dyn Bus, full: bool) -> Result<(), FTError> {
+    self.send(bus, Uid::BROADCAST, DISC_UN_MUTE, &[])?;
+    if full {
+      self.tod.clear();
+    }
+    for uid in self.tod() {
+      if !self.mute(bus, uid)? {
+        self.tod.remove(&uid);
+      }
+    }
+    self.branches = vec![(0, Uid::BROADCAST.to_u64() - 1)];
+    self.searched = 0;
+    Ok(())
+  }
+  // Searches one branch, returns true when discovery has finished.
+  pub fn step(&mut self, bus: &mut dyn Bus) -> Result<bool, FTError> {
+    let (lower, upper) = match self.branches.pop() {
+      Some(b) => b,
+      None => return Ok(true),
+    };
+    self.searched += 1;
+    if self.searched > MAX_BRANCHES {
+      println!("RDM discovery stopped after {} branches", MAX_BRANCHES);
+      self.branches.clear();
+      return Ok(true);
+    }
+    let mut bounds = Uid::from_u64(lower).0.to_vec();
+    bounds.extend_from_slice(&Uid::from_u64(upper).0);
+    let response = self.send(bus, Uid::BROADCAST, DISC_UNIQUE_BRANCH, &bounds)?;
+    if response.is_empty() {
+      return Ok(self.branches.is_empty());
+    }
+    match decode_discovery(&response) {
+      Some(uid) if self.mute(bus, uid)? => {
+        self.tod.insert(uid);
+        // others may be hiding in the same branch
+        self.branches.push((lower, upper));
+      },
+      _ if lower < upper => {
+        let middle = lower + (upper - lower) / 2;
+        self.branches.push((middle + 1, upper));
+        self.branches.push((lower, middle));
+      },
+      _ => {},
+    }
+    Ok(self.branches.is_empty())
+  }
+  // Forwards request of another controller, packet is without the start code.
+  pub fn request(&mut self, bus: &mut dyn Bus, packet: &[u8]) -> Result<Option<Vec<u8>>, FTError> {
+    let dest = match packet.get(2..8) {
+      Some(d) => Uid([d[0], d[1], d[2], d[3], d[4], d[5]]),
+      None => return Ok(None),
+    };
+    let mut p = vec![START_CODE];
+    p.extend_from_slice(packet);
+    let response = bus.transaction(&p, !dest.is_broadcast())?;
+    Ok(decode(&response).map(|r| r.to_vec()))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Responders on a line, answering like real ones: nothing to broadcasts but DISC_UNIQUE_BRANCH,
+  // colliding responses are garbled.
+  struct MockBus {
+    responders: Vec<(Uid, bool)>,
+  }
+
+  fn discovery_response(uid: Uid) -> Vec<u8> {
+    let mut r = vec![0xfe; 7];
+    r.push(0xaa);
+    let mut e: Vec<u8> = uid.0.iter().flat_map(|b| [b | 0xaa, b | 0x55]).collect();
+    let sum = checksum(&e);
+    e.extend(sum.to_be_bytes().iter().flat_map(|b| [b | 0xaa, b | 0x55]));
+    r.extend(e);
+    r
+  }
+
+  impl Bus for MockBus {
+    fn transaction(&mut self, packet: &[u8], response: bool) -> Result<Vec<u8>, FTError> {
+      let p = decode(packet).expect("valid request");
+      let dest = Uid([p[2], p[3], p[4], p[5], p[6], p[7]]);
+      let src = Uid([p[8], p[9], p[10], p[11], p[12], p[13]]);
+      let pid = u16::from_be_bytes([p[20], p[21]]);
+      let data = &p[23..23 + p[22] as usize];
+      let mut answers = Vec::new();
+      for (uid, muted) in self.responders.iter_mut() {
+        match pid {
+          DISC_UN_MUTE if dest.is_broadcast() || dest == *uid => *muted = false,
+          DISC_MUTE if dest == *uid => {
+            *muted = true;
+            answers.push(encode(src, *uid, p[14], DISCOVERY_COMMAND + 1, pid, &[0, 0]));
+          },
+          DISC_UNIQUE_BRANCH if !*muted => {
+            let lower = Uid([data[0], data[1], data[2], data[3], data[4], data[5]]).to_u64();
+            let upper = Uid([data[6], data[7], data[8], data[9], data[10], data[11]]).to_u64();
+            if (lower..=upper).contains(&uid.to_u64()) {
+              answers.push(discovery_response(*uid));
+            }
+          },
+          _ if dest == *uid => answers.push(encode(src, *uid, p[14], p[19] + 1, pid, &[1, 2])),
+          _ => {},
+        }
+      }
+      if !response {
+        return Ok(Vec::new());
+      }
+      // wired OR of everything sent at once
+      let length = answers.iter().map(|a| a.len()).max().unwrap_or(0);
+      Ok((0..length).map(|i| answers.iter().fold(0, |r, a| r | a.get(i).copied().unwrap_or(0))).collect())
+    }
+  }
+
+  fn discover(controller: &mut Controller, bus: &mut MockBus, full: bool) -> Vec<Uid> {
+    controller.start_discovery(bus, full).unwrap();
+    while !controller.step(bus).unwrap() {}
+    controller.tod()
+  }
+
+  #[test]
+  fn uids() {
+    let uid = Uid([0x7f, 0xf0, 1, 2, 3, 4]);
+    assert_eq!(Uid::from_u64(uid.to_u64()), uid);
+    assert_eq!(uid.to_string(), "7ff0:01020304");
+    assert!(Uid::BROADCAST.is_broadcast());
+    // manufacturer broadcast
+    assert!(Uid([0x7f, 0xf0, 0xff, 0xff, 0xff, 0xff]).is_broadcast());
+    assert!(!uid.is_broadcast());
+    assert_eq!(Uid::controller("A1B2C3").0[..2], [0x7f, 0xf0]);
+    assert_ne!(Uid::controller("A1B2C3"), Uid::controller("A1B2C4"));
+  }
+
+  #[test]
+  fn encode_decode_round_trip() {
+    let packet = encode(Uid([1, 2, 3, 4, 5, 6]), Uid([7, 8, 9, 10, 11, 12]), 5, GET_COMMAND, 0x0060, &[9, 8, 7]);
+    assert_eq!(packet.len(), 24 + 3 + 2);
+    assert_eq!(packet[2], 27);
+    let decoded = decode(&packet).unwrap();
+    assert_eq!(decoded, &packet[1..]);
+    assert!(is_get(decoded));
+    // leading noise is skipped
+    let mut noisy = vec![0, 0xff, START_CODE];
+    noisy.extend_from_slice(&packet);
+    assert_eq!(decode(&noisy), Some(decoded));
+  }
+
+  #[test]
+  fn decode_malformed() {
+    let packet = encode(Uid([1, 2, 3, 4, 5, 6]), Uid([7, 8, 9, 10, 11, 12]), 5, 0x30, 0x0060, &[]);
+    assert!(!is_get(&packet[1..]));
+    assert!(decode(&packet[..packet.len() - 1]).is_none());
+    assert!(decode(&[]).is_none());
+    let mut bad_sum = packet.clone();
+    *bad_sum.last_mut().unwrap() ^= 1;
+    assert!(decode(&bad_sum).is_none());
+    let mut short = packet.clone();
+    short[2] = 23;
+    assert!(decode(&short).is_none());
+    assert!(!is_get(&[]));
+  }
+
+  #[test]
+  fn discovery_responses() {
+    let uid = Uid([0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc]);
+    let response = discovery_response(uid);
+    assert_eq!(decode_discovery(&response), Some(uid));
+    // preamble may be shortened
+    assert_eq!(decode_discovery(&response[5..]), Some(uid));
+    assert!(decode_discovery(&response[..response.len() - 1]).is_none());
+    assert!(decode_discovery(&response[..7]).is_none());
+    let mut bad = response.clone();
+    bad[8] ^= 0x01;
+    assert!(decode_discovery(&bad).is_none());
+  }
+
+  #[test]
+  fn discovery_finds_every_responder() {
+    let uids = [Uid([0x12, 0x34, 0, 0, 0, 1]), Uid([0x12, 0x34, 0, 0, 0, 2]), Uid([0x7f, 0xf0, 0xde, 0xad, 0xbe, 0xef])];
+    let mut bus = MockBus { responders: uids.iter().map(|u| (*u, false)).collect() };
+    let mut controller = Controller::new(Uid::controller("test"));
+    assert_eq!(discover(&mut controller, &mut bus, true), uids.to_vec());
+    assert!(!controller.is_discovering());
+    // incremental discovery drops responders gone from the line
+    bus.responders.remove(1);
+    assert_eq!(discover(&mut controller, &mut bus, false), vec![uids[0], uids[2]]);
+  }
+
+  #[test]
+  fn discovery_of_empty_line() {
+    let mut bus = MockBus { responders: Vec::new() };
+    let mut controller = Controller::new(Uid::controller("test"));
+    assert!(discover(&mut controller, &mut bus, true).is_empty());
+  }
+
+  #[test]
+  fn requests() {
+    let uid = Uid([0x12, 0x34, 0, 0, 0, 1]);
+    let mut bus = MockBus { responders: vec![(uid, false)] };
+    let mut controller = Controller::new(Uid::controller("test"));
+    let request = encode(uid, Uid([1, 1, 1, 1, 1, 1]), 1, 0x30, 0x00f0, &[]);
+    let response = controller.request(&mut bus, &request[1..]).unwrap().unwrap();
+    assert_eq!(response[19], 0x31);
+    assert_eq!(&response[2..8], &[1, 1, 1, 1, 1, 1]);
+    // broadcasts are not answered
+    let broadcast = encode(Uid::BROADCAST, Uid([1, 1, 1, 1, 1, 1]), 2, 0x30, 0x00f0, &[]);
+    assert_eq!(controller.request(&mut bus, &broadcast[1..]).unwrap(), None);
+    assert_eq!(controller.request(&mut bus, &[0xcc]).unwrap(), None);
+  }
+}
@@ -0,0 +1,292 @@
+use std::{collections::{BTreeSet, HashMap}, net::{Ipv4Addr, UdpSocket}, sync::{Arc, atomic::Ordering}, time::Instant};
+
+use crate::{auth::Auth, osc::Command, ingest::Ingest, source::{SourceId, MAX_PRIORITY, SACN_TIMEOUT}, status::Stats};
+
+pub const PORT: u16 = 5568;
+const ACN_ID: &[u8] = b"ASC-E1.17\0\0\0";
+const VECTOR_ROOT_E131_DATA: u32 = 0x00000004;
+const VECTOR_ROOT_E131_EXTENDED: u32 = 0x00000008;
+const VECTOR_E131_DATA_PACKET: u32 = 0x00000002;
+const VECTOR_E131_EXTENDED_SYNCHRONIZATION: u32 = 0x00000001;
+const VECTOR_DMP_SET_PROPERTY: u8 = 0x02;
+const OPTION_PREVIEW: u8 = 0x80;
+const OPTION_TERMINATED: u8 = 0x40;
+const SOURCE_NAME_SIZE: usize = 64;
+pub const MAX_UNIVERSE: u16 = 63999;
+
+// 239.255.<universe high byte>.<universe low byte>
+pub fn multicast_group(universe: u16) -> Ipv4Addr {
+  Ipv4Addr::new(239, 255, (universe >> 8) as u8, universe as u8)
+}
+
+#[derive(Debug)]
+pub enum Packet<'a> {
+  Data {
+    cid: [u8; 16],
+    priority: u8,
+    // universe of the sync packets to wait for, 0 if not synchronized
+    sync_address: u16,
+    sequence: u8,
+    options: u8,
+    universe: u16,
+    start_code: u8,
+    data: &'a [u8],
+  },
+  Sync { cid: [u8; 16], sync_address: u16 },
+}
+
+fn u32_at(buf: &[u8], at: usize) -> u32 {
+  u32::from_be_bytes([buf[at], buf[at+1], buf[at+2], buf[at+3]])
+}
+
+// None for anything else than a valid E1.31 data or synchronization packet.
+pub fn parse(buf: &[u8]) -> Option<Packet<'_>> {
+  if buf.len() < 49 || buf[..4] != [0x00, 0x10, 0x00, 0x00] || &buf[4..16] != ACN_ID {
+    return None;
+  }
+  if u32_at(buf, 18) == VECTOR_ROOT_E131_EXTENDED && u32_at(buf, 40) == VECTOR_E131_EXTENDED_SYNCHRONIZATION {
+    let mut cid = [0u8; 16];
+    cid.copy_from_slice(&buf[22..38]);
+    let sync_address = u16::from_be_bytes([buf[45], buf[46]]);
+    return if sync_address != 0 { Some(Packet::Sync { cid, sync_address }) } else { None };
+  }
+  if buf.len() < 126 || u32_at(buf, 18) != VECTOR_ROOT_E131_DATA || u32_at(buf, 40) != VECTOR_E131_DATA_PACKET {
+    return None;
+  }
+  if buf[117] != VECTOR_DMP_SET_PROPERTY || buf[118] != 0xa1 {
+    return None;
+  }
+  let mut cid = [0u8; 16];
+  cid.copy_from_slice(&buf[22..38]);
+  let priority = buf[108];
+  let universe = u16::from_be_bytes([buf[113], buf[114]]);
+  let count = u16::from_be_bytes([buf[123], buf[124]]) as usize;
+  if priority > MAX_PRIORITY || !(1..=MAX_UNIVERSE).contains(&universe) || !(1..=513).contains(&count) || buf.len() < 125 + count {
+    return None;
+  }
+  Some(Packet::Data {
+    cid, priority, universe,
+    sync_address: u16::from_be_bytes([buf[109], buf[110]]),
+    sequence: buf[111],
+    options: buf[112],
+    start_code: buf[125],
+    data: &buf[126..125+count],
+  })
+}
+
+fn flags_and_length(buf: &mut [u8], at: usize) {
+  let length = (buf.len() - at) as u16;
+  buf[at..at+2].copy_from_slice(&(0x7000 | length).to_be_bytes());
+}
+
+// Stable CID of this sender.
+pub fn cid(seed: &str) -> [u8; 16] {
+  // FNV-1a with two different offsets
+  let hash = |offset: u64| seed.bytes().fold(offset, |h, b| (h ^ b as u64).wrapping_mul(0x100000001b3));
+  let mut cid = [0u8; 16];
+  cid[..8].copy_from_slice(&hash(0xcbf29ce484222325).to_be_bytes());
+  cid[8..].copy_from_slice(&hash(0x84222325cbf29ce4).to_be_bytes());
+  cid
+}
+
+pub fn data_packet(cid: &[u8; 16], source_name: &str, priority: u8, universe: u16, sequence: u8, data: &[u8]) -> Vec<u8> {
+  let mut buf = vec![0u8; 126];
+  buf[..2].copy_from_slice(&[0x00, 0x10]);
+  buf[4..16].copy_from_slice(ACN_ID);
+  buf[18..22].copy_from_slice(&VECTOR_ROOT_E131_DATA.to_be_bytes());
+  buf[22..38].copy_from_slice(cid);
+  buf[40..44].copy_from_slice(&VECTOR_E131_DATA_PACKET.to_be_bytes());
+  let name = source_name.as_bytes();
+  let n = name.len().min(SOURCE_NAME_SIZE - 1);
+  buf[44..44+n].copy_from_slice(&name[..n]);
+  buf[108] = priority;
+  buf[111] = sequence;
+  buf[113..115].copy_from_slice(&universe.to_be_bytes());
+  buf[117] = VECTOR_DMP_SET_PROPERTY;
+  buf[118] = 0xa1;
+  // first property address 0, increment 1
+  buf[122] = 0x01;
+  buf[123..125].copy_from_slice(&(1 + data.len() as u16).to_be_bytes());
+  buf.extend_from_slice(data);
+  flags_and_length(&mut buf, 16);
+  flags_and_length(&mut buf, 38);
+  flags_and_length(&mut buf, 115);
+  buf
+}
+
+struct Stream {
+  sequence: u8,
+  priority: u8,
+  seen: Instant,
+}
+
+// Receiver of one universe, every stream (CID) is a separate source with the priority it sends.
+pub struct Receiver {
+  sock: UdpSocket,
+  universe: u16,
+  interface: Ipv4Addr,
+  // sync universes used by the streams, their groups are joined
+  sync_addresses: BTreeSet<u16>,
+  size: usize,
+  // --allow list, packets of other senders are counted as rejected
+  auth: Auth,
+  ingest: Ingest,
+  streams: HashMap<[u8; 16], Stream>,
+  stats: Arc<Stats>,
+}
+
+impl Receiver {
+  pub fn new(sock: UdpSocket, universe: u16, interface: Ipv4Addr, size: usize, auth: Auth, ingest: Ingest, stats: Arc<Stats>) -> Receiver {
+    Receiver { sock, universe, interface, sync_addresses: BTreeSet::new(), size, auth, ingest, streams: HashMap::new(), stats }
+  }
+  fn use_sync_address(&mut self, sync_address: u16) {
+    if sync_address == 0 || !self.sync_addresses.insert(sync_address) {
+      return;
+    }
+    let group = multicast_group(sync_address);
+    if sync_address != self.universe {
+      if let Err(e) = self.sock.join_multicast_v4(&group, &self.interface) {
+        println!("Can't join sACN sync group {}: {}", group, e);
+      }
+    }
+  }
+  // E1.31 6.7.2: packets up to 20 behind the last one are out of order.
+  fn in_sequence(&self, cid: &[u8; 16], sequence: u8) -> bool {
+    match self.streams.get(cid) {
+      Some(s) => {
+        let diff = sequence.wrapping_sub(s.sequence) as i8;
+        diff > 0 || diff <= -20
+      },
+      None => true,
+    }
+  }
+  pub fn run(&mut self) {
+    let mut buf = [0u8; 1144];
+    loop {
+      self.ingest.flush();
+      let size = match self.sock.recv_from(&mut buf) {
+        Ok((_, addr)) if self.auth.check_address(addr.ip()).is_err() => {
+          self.stats.rejected.fetch_add(1, Ordering::Relaxed);
+          continue;
+        },
+        Ok((size, _)) => size,
+        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock || e.kind() == std::io::ErrorKind::TimedOut => continue,
+        Err(e) => {
+          println!("Error receiving sACN: {}", e);
+          std::process::exit(1);
+        }
+      };
+      let (cid, priority, sync_address, sequence, options, start_code, data) = match parse(&buf[..size]) {
+        Some(Packet::Data { cid, priority, sync_address, sequence, options, universe, start_code, data }) if universe == self.universe => {
+          (cid, priority, sync_address, sequence, options, start_code, data)
+        },
+        Some(Packet::Sync { cid, sync_address }) if self.sync_addresses.contains(&sync_address) => {
+          self.ingest.push(SourceId::Sacn(cid), Command::Sync);
+          continue;
+        },
+        _ => continue,
+      };
+      if !self.in_sequence(&cid, sequence) {
+        continue;
+      }
+      let source = SourceId::Sacn(cid);
+      if options & OPTION_TERMINATED != 0 {
+        if self.streams.remove(&cid).is_some() {
+          self.ingest.push(source, Command::Release);
+        }
+        continue;
+      }
+      if options & OPTION_PREVIEW != 0 || start_code != 0 {
+        continue;
+      }
+      self.stats.sacn.fetch_add(1, Ordering::Relaxed);
+      self.use_sync_address(sync_address);
+      let now = Instant::now();
+      // the output loop has released streams silent for longer than the timeout
+      let known = self.streams.insert(cid, Stream { sequence, priority, seen: now })
+        .filter(|s| now.saturating_duration_since(s.seen) < SACN_TIMEOUT);
+      // tried again with the next packet when the queue is full
+      if known.map(|s| s.priority) != Some(priority) && !self.ingest.push(source, Command::Priority(priority)) {
+        self.streams.remove(&cid);
+      }
+      let values = data.iter().take(self.size).map(|v| Some(*v)).collect();
+      self.ingest.push(source, Command::Set { address: 0, values });
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn sync_packet(cid: &[u8; 16], sync_address: u16) -> Vec<u8> {
+    let mut buf = vec![0u8; 49];
+    buf[..2].copy_from_slice(&[0x00, 0x10]);
+    buf[4..16].copy_from_slice(ACN_ID);
+    buf[18..22].copy_from_slice(&VECTOR_ROOT_E131_EXTENDED.to_be_bytes());
+    buf[22..38].copy_from_slice(cid);
+    buf[40..44].copy_from_slice(&VECTOR_E131_EXTENDED_SYNCHRONIZATION.to_be_bytes());
+    buf[45..47].copy_from_slice(&sync_address.to_be_bytes());
+    flags_and_length(&mut buf, 16);
+    flags_and_length(&mut buf, 38);
+    buf
+  }
+
+  #[test]
+  fn data_round_trip() {
+    let id = cid("test");
+    let values: Vec<u8> = (0..=255).chain(0..=255).collect();
+    let buf = data_packet(&id, "roscdmx", 150, 63999, 42, &values);
+    assert_eq!(buf.len(), 126 + 512);
+    match parse(&buf) {
+      Some(Packet::Data { cid: c, priority, sync_address, sequence, options, universe, start_code, data }) => {
+        assert_eq!(c, id);
+        assert_eq!((priority, sync_address, sequence, options, universe, start_code), (150, 0, 42, 0, 63999, 0));
+        assert_eq!(data, &values[..]);
+      },
+      p => panic!("unexpected {:?}", p),
+    }
+    // start code only
+    assert!(matches!(parse(&data_packet(&id, "", 100, 1, 0, &[])), Some(Packet::Data { data: [], .. })));
+  }
+
+  #[test]
+  fn malformed_data() {
+    let id = cid("test");
+    let buf = data_packet(&id, "roscdmx", 100, 1, 0, &[1, 2, 3]);
+    assert!(parse(&buf[..buf.len() - 1]).is_none());
+    assert!(parse(&buf[..125]).is_none());
+    assert!(parse(&buf[..48]).is_none());
+    let set = |at: usize, v: &[u8]| {
+      let mut b = buf.clone();
+      b[at..at+v.len()].copy_from_slice(v);
+      b
+    };
+    assert!(parse(&set(4, b"ASC-E1.18")).is_none());
+    assert!(parse(&set(108, &[MAX_PRIORITY + 1])).is_none());
+    assert!(parse(&set(113, &0_u16.to_be_bytes())).is_none());
+    assert!(parse(&set(113, &64000_u16.to_be_bytes())).is_none());
+    assert!(parse(&set(123, &0_u16.to_be_bytes())).is_none());
+    assert!(parse(&set(123, &514_u16.to_be_bytes())).is_none());
+    assert!(parse(&set(40, &VECTOR_E131_EXTENDED_SYNCHRONIZATION.to_be_bytes())).is_none());
+    assert!(parse(&set(117, &[0x01])).is_none());
+  }
+
+  #[test]
+  fn sync() {
+    let id = cid("test");
+    match parse(&sync_packet(&id, 7)) {
+      Some(Packet::Sync { cid: c, sync_address }) => assert_eq!((c, sync_address), (id, 7)),
+      p => panic!("unexpected {:?}", p),
+    }
+    assert!(parse(&sync_packet(&id, 0)).is_none());
+    assert!(parse(&sync_packet(&id, 7)[..48]).is_none());
+  }
+
+  #[test]
+  fn cids_and_groups() {
+    assert_eq!(cid("a"), cid("a"));
+    assert_ne!(cid("a"), cid("b"));
+    assert_eq!(multicast_group(0x1234), Ipv4Addr::new(239, 255, 0x12, 0x34));
+  }
+}
@@ -0,0 +1,53 @@
+use crate::patch::Patch;
+
+// Per channel guarantees. Limits and parks are keyed by output slot and applied to the output frame
+// after curves and routing: values are clamped to limits and parked slots are forced to their value.
+// Locked channels are logical channels ignoring writes from the network.
+pub struct Safety {
+  limits: Vec<(u8, u8)>,
+  parked: Vec<Option<u8>>,
+  locked: Vec<bool>,
+}
+
+impl Safety {
+  pub fn new(patch: &Patch, size: usize) -> Safety {
+    Safety {
+      limits: (0..size).map(|c| patch.limits.get(&c).copied().unwrap_or((0, 255))).collect(),
+      parked: (0..size).map(|c| patch.parked.get(&c).copied()).collect(),
+      locked: (0..size).map(|c| patch.locked.contains(&c)).collect(),
+    }
+  }
+  pub fn is_locked(&self, channel: usize) -> bool {
+    self.locked.get(channel).copied().unwrap_or(false)
+  }
+  pub fn set_limit(&mut self, channel: usize, min: u8, max: u8) -> bool {
+    if channel >= self.limits.len() || min > max {
+      return false;
+    }
+    self.limits[channel] = (min, max);
+    true
+  }
+  pub fn set_parked(&mut self, channel: usize, value: Option<u8>) -> bool {
+    if channel >= self.parked.len() {
+      return false;
+    }
+    self.parked[channel] = value;
+    true
+  }
+  pub fn set_locked(&mut self, channel: usize, locked: bool) -> bool {
+    if channel >= self.locked.len() {
+      return false;
+    }
+    self.locked[channel] = locked;
+    true
+  }
+  pub fn apply(&self, frame: &mut [u8]) {
+    for (c, v) in frame.iter_mut().enumerate() {
+      let (min, max) = self.limits[c];
+      *v = match self.parked[c] {
+        Some(p) => p,
+        None => (*v).clamp(min, max),
+      };
+    }
+  }
+}
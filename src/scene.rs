@@ -0,0 +1,106 @@
+use std::{fmt, error::Error, collections::BTreeMap, io::Write, sync::mpsc::{channel, Sender}};
+
+use crate::hex;
+
+#[derive(Debug)]
+pub enum SceneError {
+  IOError(std::io::Error),
+  Syntax(usize, String),
+}
+impl fmt::Display for SceneError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      SceneError::IOError(e) => write!(f, "Scene file error: {}", e),
+      SceneError::Syntax(line, e) => write!(f, "Scene file line {}: {}", line, e),
+    }
+  }
+}
+impl Error for SceneError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      SceneError::IOError(e) => Some(e),
+      SceneError::Syntax(_, _) => None,
+    }
+  }
+}
+impl From<std::io::Error> for SceneError {
+  fn from(e: std::io::Error) -> Self {
+    SceneError::IOError(e)
+  }
+}
+
+fn save(path: &str, scenes: &BTreeMap<String, Vec<u8>>) -> Result<(), SceneError> {
+  let tmp = path.to_string() + ".tmp";
+  {
+    let mut f = std::fs::File::create(&tmp)?;
+    for (name, data) in scenes {
+      writeln!(f, "{} {}", name, hex::encode(data))?;
+    }
+    f.sync_all()?;
+  }
+  std::fs::rename(&tmp, path)?;
+  Ok(())
+}
+
+// Writes the file in its own thread, so a slow disk doesn't hold the DMX output; only the latest state is written.
+fn writer(path: String) -> Sender<BTreeMap<String, Vec<u8>>> {
+  let (sender, received) = channel::<BTreeMap<String, Vec<u8>>>();
+  std::thread::spawn(move || {
+    while let Ok(scenes) = received.recv() {
+      let scenes = received.try_iter().last().unwrap_or(scenes);
+      if let Err(e) = save(&path, &scenes) {
+        println!("Can't save scenes: {}", e);
+      }
+    }
+  });
+  sender
+}
+
+// Scene file has one scene per line: "<name> <channel values in hex>"
+#[derive(Debug, Default)]
+pub struct Scenes {
+  scenes: BTreeMap<String, Vec<u8>>,
+  // None keeps scenes in memory only
+  writer: Option<Sender<BTreeMap<String, Vec<u8>>>>,
+}
+
+impl Scenes {
+  // Missing file is not an error, it will be created on first record.
+  pub fn load(path: &str) -> Result<Scenes, SceneError> {
+    let mut scenes = Scenes { scenes: BTreeMap::new(), writer: None };
+    if path.is_empty() {
+      return Ok(scenes);
+    }
+    scenes.writer = Some(writer(path.to_string()));
+    let text = match std::fs::read_to_string(path) {
+      Ok(t) => t,
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(scenes),
+      Err(e) => return Err(e.into()),
+    };
+    for (i, line) in text.lines().enumerate() {
+      let mut words = line.split_whitespace();
+      let name = match words.next() {
+        Some(n) => n,
+        None => continue,
+      };
+      let data = words.next().and_then(hex::decode)
+        .ok_or_else(|| SceneError::Syntax(i+1, format!("bad data for scene {:?}", name)))?;
+      scenes.scenes.insert(name.to_string(), data);
+    }
+    Ok(scenes)
+  }
+  pub fn valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains(char::is_whitespace)
+  }
+  pub fn record(&mut self, name: &str, data: &[u8]) {
+    self.scenes.insert(name.to_string(), data.to_vec());
+    if let Some(writer) = &self.writer {
+      if writer.send(self.scenes.clone()).is_err() {
+        println!("Scene writer has stopped, scene {} is not saved", name);
+      }
+    }
+  }
+  pub fn get(&self, name: &str) -> Option<&Vec<u8>> {
+    self.scenes.get(name)
+  }
+}
@@ -0,0 +1,341 @@
+use std::{collections::{BTreeMap, HashMap}, net::{SocketAddr, IpAddr}, time::{Duration, Instant}};
+
+use crate::{patch::{Patch, Attribute16}, fade::{Fades, Target, Profile}};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SourceId {
+  // OSC senders are merged by address, see Sources::key
+  Osc(SocketAddr),
+  ArtNet(SocketAddr),
+  // sACN stream by its CID
+  Sacn([u8; 16]),
+  // HTTP clients by address only, every request may come from another port
+  Http(IpAddr),
+  // the MQTT broker, all its clients together
+  Mqtt,
+  // scenes and cues
+  Playback,
+  // scene shown when nothing else is active
+  DefaultLook,
+}
+
+pub const DEFAULT_PRIORITY: u8 = 100;
+pub const MAX_PRIORITY: u8 = 200;
+// E1.31 network data loss timeout
+pub const SACN_TIMEOUT: Duration = Duration::from_millis(2500);
+
+#[derive(Debug, Copy, Clone)]
+pub struct SourceSettings {
+  pub priority: u8,
+  // source is released if it doesn't send anything for that long
+  pub timeout: Option<Duration>,
+}
+
+pub struct SourceConfig {
+  pub default: SourceSettings,
+  pub per_ip: HashMap<IpAddr, SourceSettings>,
+}
+
+impl SourceConfig {
+  // "<ip>=<priority>[/<timeout seconds>]"
+  pub fn add_spec(&mut self, spec: &str) -> Option<()> {
+    let (ip, settings) = spec.split_once('=')?;
+    let (priority, timeout) = match settings.split_once('/') {
+      Some((p, t)) => (p, Some(t)),
+      None => (settings, None),
+    };
+    let priority = priority.parse::<u8>().ok().filter(|p| *p <= MAX_PRIORITY)?;
+    let timeout = match timeout {
+      Some(t) => seconds(t.parse::<f64>().ok()?)?,
+      None => self.default.timeout,
+    };
+    self.per_ip.insert(ip.parse().ok()?, SourceSettings { priority, timeout });
+    Some(())
+  }
+}
+
+// zero means no timeout, None for negative, NaN or too long ones
+pub fn seconds(s: f64) -> Option<Option<Duration>> {
+  if s == 0_f64 {
+    Some(None)
+  } else {
+    Some(Some(Duration::try_from_secs_f64(s).ok()?))
+  }
+}
+
+pub struct Source {
+  data: Vec<u8>,
+  // stamp of the last write per channel, 0 if never written
+  written: Vec<u64>,
+  fades: Fades,
+  settings: SourceSettings,
+  last_seen: Instant,
+}
+
+impl Source {
+  fn new(size: usize, settings: SourceSettings) -> Source {
+    Source {
+      data: vec![0; size],
+      written: vec![0; size],
+      fades: Fades::new(),
+      settings,
+      last_seen: Instant::now(),
+    }
+  }
+  pub fn set(&mut self, channel: usize, value: u8, stamp: u64) {
+    if channel < self.data.len() {
+      self.fades.cancel(channel);
+      self.data[channel] = value;
+      self.written[channel] = stamp;
+    }
+  }
+  pub fn set16(&mut self, attribute: Attribute16, value: u16, stamp: u64) {
+    self.fades.cancel(attribute.coarse);
+    self.fades.cancel(attribute.fine);
+    attribute.write(&mut self.data, value);
+    for c in [attribute.coarse, attribute.fine] {
+      if c < self.written.len() {
+        self.written[c] = stamp;
+      }
+    }
+  }
+  pub fn fade(&mut self, target: Target, to: f32, time: Duration, profile: Profile, stamp: u64) {
+    self.fades.start(&self.data, target, to, time, profile);
+    for c in target.channels() {
+      if c < self.written.len() {
+        self.written[c] = stamp;
+      }
+    }
+  }
+}
+
+// Per-sender buffers. Only sources with the highest priority are merged into the frame:
+// highest value wins on HTP channels, latest write wins on LTP ones.
+pub struct Sources {
+  sources: BTreeMap<SourceId, Source>,
+  config: SourceConfig,
+  htp: Vec<bool>,
+  stamp: u64,
+}
+
+impl Sources {
+  pub fn new(patch: &Patch, size: usize, config: SourceConfig) -> Sources {
+    Sources {
+      sources: BTreeMap::new(),
+      config,
+      htp: (0..size).map(|c| patch.is_htp(c)).collect(),
+      stamp: 0,
+    }
+  }
+  // Lowest priority layer which never times out.
+  pub fn set_default_look(&mut self, look: &[u8]) {
+    let mut source = Source::new(self.htp.len(), SourceSettings { priority: 0, timeout: None });
+    for (c, v) in look.iter().enumerate().take(self.htp.len()) {
+      source.data[c] = *v;
+      source.written[c] = 1;
+    }
+    self.sources.insert(SourceId::DefaultLook, source);
+  }
+  pub fn next_stamp(&mut self) -> u64 {
+    self.stamp += 1;
+    self.stamp
+  }
+  // Clients may send every message from another port, one buffer per OSC sender address.
+  pub fn key(id: SourceId) -> SourceId {
+    match id {
+      SourceId::Osc(addr) => SourceId::Osc(SocketAddr::new(addr.ip(), 0)),
+      id => id,
+    }
+  }
+  fn settings(&self, id: SourceId) -> SourceSettings {
+    match id {
+      SourceId::Osc(addr) | SourceId::ArtNet(addr) => self.config.per_ip.get(&addr.ip()).copied().unwrap_or(self.config.default),
+      SourceId::Http(ip) => self.config.per_ip.get(&ip).copied().unwrap_or(self.config.default),
+      SourceId::Sacn(_) => SourceSettings { priority: DEFAULT_PRIORITY, timeout: Some(SACN_TIMEOUT) },
+      SourceId::Mqtt => SourceSettings { priority: self.config.default.priority, timeout: None },
+      SourceId::Playback => SourceSettings { priority: self.config.default.priority, timeout: None },
+      SourceId::DefaultLook => SourceSettings { priority: 0, timeout: None },
+    }
+  }
+  pub fn get(&mut self, id: SourceId) -> &mut Source {
+    let id = Sources::key(id);
+    let size = self.htp.len();
+    let settings = self.settings(id);
+    let source = self.sources.entry(id).or_insert_with(|| Source::new(size, settings));
+    source.last_seen = Instant::now();
+    source
+  }
+  // For sources sending their priority along with the data.
+  pub fn set_priority(&mut self, id: SourceId, priority: u8) {
+    self.get(id).settings.priority = priority;
+  }
+  pub fn release(&mut self, id: SourceId) {
+    let id = Sources::key(id);
+    if self.sources.remove(&id).is_some() {
+      println!("Source {:?} released", id);
+    }
+  }
+  // Fades playback to the scene. LTP channels start from the current output,
+  // so the look crossfades regardless of which source owned them.
+  pub fn recall(&mut self, current: &[u8], scene: &[u8], fade_in: Duration, fade_out: Duration) {
+    let stamp = self.next_stamp();
+    let size = self.htp.len();
+    let settings = self.settings(SourceId::Playback);
+    let playback = self.sources.entry(SourceId::Playback).or_insert_with(|| Source::new(size, settings));
+    for (c, v) in scene.iter().enumerate().take(size) {
+      if !self.htp[c] {
+        playback.data[c] = current[c];
+      }
+      let time = if *v >= current[c] { fade_in } else { fade_out };
+      playback.fade(Target::Channel(c), *v as f32, time, Profile::Linear, stamp);
+    }
+  }
+  pub fn update(&mut self, now: Instant) {
+    self.sources.retain(|id, s| {
+      let active = s.settings.timeout.is_none_or(|t| now.saturating_duration_since(s.last_seen) < t);
+      if !active {
+        println!("Source {:?} timed out, releasing", id);
+      }
+      active
+    });
+    for s in self.sources.values_mut() {
+      s.fades.update(&mut s.data, now);
+    }
+  }
+  pub fn merge(&self, out: &mut [u8]) {
+    let priority = self.sources.values().map(|s| s.settings.priority).max().unwrap_or(0);
+    let owners: Vec<&Source> = self.sources.values().filter(|s| s.settings.priority == priority).collect();
+    for (c, o) in out.iter_mut().enumerate() {
+      *o = if self.htp[c] {
+        owners.iter().map(|s| s.data[c]).max().unwrap_or(0)
+      } else {
+        owners.iter()
+          .filter(|s| s.written[c] > 0)
+          .max_by_key(|s| s.written[c])
+          .map_or(0, |s| s.data[c])
+      };
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const SIZE: usize = 4;
+
+  fn sources(patch: &str, default: SourceSettings) -> Sources {
+    Sources::new(&Patch::parse(patch).unwrap(), SIZE, SourceConfig { default, per_ip: HashMap::new() })
+  }
+
+  fn osc(addr: &str) -> SourceId {
+    SourceId::Osc(addr.parse().unwrap())
+  }
+
+  fn set(sources: &mut Sources, id: SourceId, channel: usize, value: u8) {
+    let stamp = sources.next_stamp();
+    sources.get(id).set(channel, value, stamp);
+  }
+
+  fn merged(sources: &Sources) -> Vec<u8> {
+    let mut out = vec![0; SIZE];
+    sources.merge(&mut out);
+    out
+  }
+
+  #[test]
+  fn htp_takes_the_highest_value() {
+    let mut sources = sources("", SourceSettings { priority: DEFAULT_PRIORITY, timeout: None });
+    set(&mut sources, osc("10.0.0.1:9000"), 0, 100);
+    set(&mut sources, osc("10.0.0.2:9000"), 0, 50);
+    set(&mut sources, osc("10.0.0.2:9000"), 1, 70);
+    assert_eq!(merged(&sources), vec![100, 70, 0, 0]);
+  }
+
+  #[test]
+  fn ltp_takes_the_latest_write() {
+    let mut sources = sources("intensity 0\n", SourceSettings { priority: DEFAULT_PRIORITY, timeout: None });
+    set(&mut sources, osc("10.0.0.1:9000"), 1, 200);
+    set(&mut sources, osc("10.0.0.2:9000"), 1, 50);
+    assert_eq!(merged(&sources), vec![0, 50, 0, 0]);
+    set(&mut sources, osc("10.0.0.1:9000"), 1, 150);
+    assert_eq!(merged(&sources), vec![0, 150, 0, 0]);
+    // untouched by the other sender
+    set(&mut sources, osc("10.0.0.2:9000"), 2, 10);
+    assert_eq!(merged(&sources), vec![0, 150, 10, 0]);
+  }
+
+  #[test]
+  fn merge_overrides() {
+    // channel 0 is intensity but LTP, channel 2 isn't but HTP
+    let mut sources = sources("intensity 0-1\nltp 0\nhtp 2\n", SourceSettings { priority: DEFAULT_PRIORITY, timeout: None });
+    for (channel, first, second) in [(0, 200, 50), (1, 200, 50), (2, 200, 50), (3, 200, 50)] {
+      set(&mut sources, osc("10.0.0.1:9000"), channel, first);
+      set(&mut sources, osc("10.0.0.2:9000"), channel, second);
+    }
+    assert_eq!(merged(&sources), vec![50, 200, 200, 50]);
+  }
+
+  #[test]
+  fn ports_of_one_address_share_a_buffer() {
+    let mut sources = sources("", SourceSettings { priority: DEFAULT_PRIORITY, timeout: None });
+    set(&mut sources, osc("10.0.0.1:9000"), 0, 200);
+    set(&mut sources, osc("10.0.0.1:9001"), 0, 20);
+    set(&mut sources, osc("10.0.0.1:9002"), 1, 30);
+    // the later write replaces the value instead of being merged HTP
+    assert_eq!(merged(&sources), vec![20, 30, 0, 0]);
+    assert_eq!(sources.sources.len(), 1);
+  }
+
+  #[test]
+  fn timeouts() {
+    assert_eq!(seconds(0_f64), Some(None));
+    assert_eq!(seconds(2.5), Some(Some(Duration::from_millis(2500))));
+    assert_eq!(seconds(-1_f64), None);
+    assert_eq!(seconds(f64::NAN), None);
+    assert_eq!(seconds(f64::INFINITY), None);
+    assert_eq!(seconds(1e30), None);
+  }
+
+  #[test]
+  fn silent_sources_are_released() {
+    let mut sources = sources("", SourceSettings { priority: DEFAULT_PRIORITY, timeout: Some(Duration::from_secs(1)) });
+    sources.set_default_look(&[10, 20, 30, 40]);
+    set(&mut sources, osc("10.0.0.1:9000"), 0, 200);
+    let now = Instant::now();
+    sources.update(now);
+    // only the highest priority is merged, the default look is hidden
+    assert_eq!(merged(&sources), vec![200, 0, 0, 0]);
+    sources.update(now + Duration::from_secs(2));
+    assert_eq!(merged(&sources), vec![10, 20, 30, 40]);
+  }
+
+  #[test]
+  fn higher_priority_wins_until_released() {
+    let mut sources = sources("", SourceSettings { priority: DEFAULT_PRIORITY, timeout: None });
+    sources.set_default_look(&[10, 20, 30, 40]);
+    set(&mut sources, osc("10.0.0.1:9000"), 0, 200);
+    let sacn = SourceId::Sacn([1; 16]);
+    sources.set_priority(sacn, 150);
+    set(&mut sources, sacn, 1, 50);
+    assert_eq!(merged(&sources), vec![0, 50, 0, 0]);
+    sources.release(sacn);
+    assert_eq!(merged(&sources), vec![200, 0, 0, 0]);
+    // without timeout a source stays until released
+    sources.update(Instant::now() + Duration::from_secs(3600));
+    assert_eq!(merged(&sources), vec![200, 0, 0, 0]);
+    sources.release(osc("10.0.0.1:9001"));
+    assert_eq!(merged(&sources), vec![10, 20, 30, 40]);
+  }
+
+  #[test]
+  fn playback_stays_until_released() {
+    let mut sources = sources("", SourceSettings { priority: DEFAULT_PRIORITY, timeout: Some(Duration::from_secs(1)) });
+    sources.set_default_look(&[10, 20, 30, 40]);
+    sources.recall(&[0; SIZE], &[1, 2, 3, 4], Duration::ZERO, Duration::ZERO);
+    sources.update(Instant::now() + Duration::from_secs(3600));
+    assert_eq!(merged(&sources), vec![1, 2, 3, 4]);
+    sources.release(SourceId::Playback);
+    assert_eq!(merged(&sources), vec![10, 20, 30, 40]);
+  }
+}
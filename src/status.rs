@@ -0,0 +1,100 @@
+use std::{sync::atomic::{AtomicBool, AtomicU64, Ordering}, time::{Duration, Instant}};
+
+use crate::ftd2xx::FTError;
+
+// Counters of the receiving threads.
+#[derive(Default)]
+pub struct Stats {
+  pub received: AtomicU64,
+  pub rejected: AtomicU64,
+  pub dropped: AtomicU64,
+  pub coalesced: AtomicU64,
+  pub errors: AtomicU64,
+  // accepted ArtDmx packets
+  pub artnet: AtomicU64,
+  // accepted E1.31 data packets
+  pub sacn: AtomicU64,
+  // last write to the device succeeded, set by the output loop
+  pub device_open: AtomicBool,
+}
+
+// State of the output loop, reported by "/roscdmx/status".
+pub struct Health {
+  started: Instant,
+  serial: String,
+  open: bool,
+  frames: u64,
+  failed_writes: u64,
+  dropped_writes: u64,
+  last_error: Option<String>,
+  fps: f64,
+  window_start: Instant,
+  window_frames: u64,
+}
+
+impl Health {
+  pub fn new(serial: String) -> Health {
+    let now = Instant::now();
+    Health {
+      started: now,
+      serial,
+      open: true,
+      frames: 0,
+      failed_writes: 0,
+      dropped_writes: 0,
+      last_error: None,
+      fps: 0_f64,
+      window_start: now,
+      window_frames: 0,
+    }
+  }
+  // Returns true if the state of the device has changed.
+  pub fn frame(&mut self, result: &Result<usize, FTError>, expected: usize) -> bool {
+    let was_open = self.open;
+    match result {
+      Ok(written) => {
+        self.frames += 1;
+        self.window_frames += 1;
+        if *written < expected {
+          self.dropped_writes += 1;
+        }
+        self.open = true;
+      },
+      Err(e) => {
+        self.failed_writes += 1;
+        self.last_error = Some(e.to_string());
+        self.open = false;
+      },
+    }
+    let elapsed = self.window_start.elapsed();
+    if elapsed >= Duration::from_secs(1) {
+      self.fps = self.window_frames as f64 / elapsed.as_secs_f64();
+      self.window_start = Instant::now();
+      self.window_frames = 0;
+    }
+    was_open != self.open
+  }
+  pub fn message(&self, stats: &Stats) -> rosc::OscMessage {
+    use rosc::OscType::{String as S, Long, Double, Bool};
+    rosc::OscMessage {
+      addr: "/roscdmx/status".to_string(),
+      args: vec![
+        S("serial".to_string()), S(self.serial.clone()),
+        S("open".to_string()), Bool(self.open),
+        S("fps".to_string()), Double(self.fps),
+        S("frames".to_string()), Long(self.frames as i64),
+        S("failed_writes".to_string()), Long(self.failed_writes as i64),
+        S("dropped_writes".to_string()), Long(self.dropped_writes as i64),
+        S("last_error".to_string()), S(self.last_error.clone().unwrap_or_default()),
+        S("received".to_string()), Long(stats.received.load(Ordering::Relaxed) as i64),
+        S("rejected".to_string()), Long(stats.rejected.load(Ordering::Relaxed) as i64),
+        S("dropped".to_string()), Long(stats.dropped.load(Ordering::Relaxed) as i64),
+        S("coalesced".to_string()), Long(stats.coalesced.load(Ordering::Relaxed) as i64),
+        S("errors".to_string()), Long(stats.errors.load(Ordering::Relaxed) as i64),
+        S("artnet".to_string()), Long(stats.artnet.load(Ordering::Relaxed) as i64),
+        S("sacn".to_string()), Long(stats.sacn.load(Ordering::Relaxed) as i64),
+        S("uptime".to_string()), Double(self.started.elapsed().as_secs_f64()),
+      ],
+    }
+  }
+}
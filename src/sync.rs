@@ -0,0 +1,38 @@
+use std::time::{Duration, Instant};
+
+// Holds rendered frames back while the senders synchronize output with ArtSync or E1.31 sync packets.
+// Output runs freely again if no sync arrives for `timeout`.
+pub struct Synchronizer {
+  timeout: Option<Duration>,
+  last_sync: Option<Instant>,
+}
+
+impl Synchronizer {
+  // None ignores sync packets
+  pub fn new(timeout: Option<Duration>) -> Synchronizer {
+    Synchronizer { timeout, last_sync: None }
+  }
+  // Returns true if the rendered frame should replace the one being sent.
+  pub fn frame(&mut self, now: Instant, synced: bool) -> bool {
+    let timeout = match self.timeout {
+      Some(t) => t,
+      None => return true,
+    };
+    if synced {
+      if self.last_sync.is_none() {
+        println!("Output synchronized");
+      }
+      self.last_sync = Some(now);
+      return true;
+    }
+    match self.last_sync {
+      Some(t) if now.saturating_duration_since(t) < timeout => false,
+      Some(_) => {
+        println!("No sync for {:?}, output runs freely", timeout);
+        self.last_sync = None;
+        true
+      },
+      None => true,
+    }
+  }
+}
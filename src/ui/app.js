@@ -0,0 +1,142 @@
+"use strict";
+
+// channel writes waiting to be sent, channel -> value
+const pending = new Map();
+// channels being dragged, not updated from the stream
+const touched = new Set();
+let universe = null;
+let faders = [];
+let blackout = false;
+
+async function request(method, path, body) {
+  const response = await fetch(path, {
+    method,
+    headers: { "Content-Type": "application/json" },
+    body: body === undefined ? undefined : JSON.stringify(body),
+  });
+  const json = await response.json();
+  if (!response.ok) {
+    throw new Error(json.error);
+  }
+  return json;
+}
+
+function createFaders(values) {
+  const main = document.getElementById("faders");
+  main.textContent = "";
+  faders = values.map((value, channel) => {
+    const fader = document.createElement("div");
+    fader.className = "fader";
+    const label = document.createElement("span");
+    label.textContent = channel;
+    const input = document.createElement("input");
+    input.type = "range";
+    input.min = 0;
+    input.max = 255;
+    input.value = value;
+    const current = document.createElement("span");
+    current.textContent = value;
+    const output = document.createElement("span");
+    output.className = "output";
+    input.addEventListener("pointerdown", () => touched.add(channel));
+    input.addEventListener("pointerup", () => touched.delete(channel));
+    // scrolling on touch screens cancels the pointer instead
+    input.addEventListener("pointercancel", () => touched.delete(channel));
+    input.addEventListener("lostpointercapture", () => touched.delete(channel));
+    input.addEventListener("input", () => {
+      current.textContent = input.value;
+      pending.set(channel, Number(input.value));
+    });
+    fader.append(label, input, current, output);
+    main.append(fader);
+    return { input, current, output };
+  });
+}
+
+// Sends pending writes as contiguous ranges, null fills the gaps.
+async function flush() {
+  if (pending.size === 0) {
+    return;
+  }
+  const channels = [...pending.keys()];
+  const start = Math.min(...channels);
+  const values = new Array(Math.max(...channels) - start + 1).fill(null);
+  for (const [channel, value] of pending) {
+    values[channel - start] = value;
+  }
+  pending.clear();
+  try {
+    await request("PUT", `/universes/${universe}/channels?start=${start}`, values);
+  } catch (e) {
+    console.log("Can't set channels:", e);
+  }
+}
+
+function showBlackout(on) {
+  blackout = on;
+  document.getElementById("blackout").classList.toggle("on", on);
+}
+
+function connect() {
+  const protocol = location.protocol === "https:" ? "wss:" : "ws:";
+  const ws = new WebSocket(`${protocol}//${location.host}/universes/${universe}/ws`);
+  const connection = document.getElementById("connection");
+  ws.onopen = () => {
+    connection.textContent = "live";
+    connection.className = "on";
+  };
+  ws.onmessage = (event) => {
+    const message = JSON.parse(event.data);
+    message.values.forEach((value, channel) => {
+      const fader = faders[channel];
+      if (!fader) {
+        return;
+      }
+      if (!touched.has(channel) && !pending.has(channel)) {
+        fader.input.value = value;
+        fader.current.textContent = value;
+      }
+      fader.output.textContent = message.output[channel];
+    });
+    showBlackout(message.blackout);
+  };
+  ws.onclose = () => {
+    connection.textContent = "offline";
+    connection.className = "off";
+    setTimeout(connect, 2000);
+  };
+}
+
+async function updateStatus() {
+  try {
+    const status = await request("GET", "/status");
+    const table = document.getElementById("status-table");
+    table.textContent = "";
+    for (const [name, value] of Object.entries(status)) {
+      const row = table.insertRow();
+      row.insertCell().textContent = name;
+      row.insertCell().textContent = value;
+    }
+    const device = document.getElementById("device");
+    device.textContent = status.open ? "open" : "closed";
+    device.className = status.open ? "on" : "off";
+  } catch (e) {
+    console.log("Can't get status:", e);
+  }
+}
+
+async function start() {
+  [universe] = await request("GET", "/universes");
+  document.getElementById("universe").textContent = `universe ${universe}`;
+  const channels = await request("GET", `/universes/${universe}/channels`);
+  createFaders(channels.values);
+  document.getElementById("blackout").addEventListener("click", () => {
+    request("PUT", "/blackout", !blackout).catch((e) => console.log("Can't set blackout:", e));
+  });
+  connect();
+  updateStatus();
+  setInterval(flush, 50);
+  setInterval(updateStatus, 1000);
+}
+
+start();
@@ -0,0 +1,191 @@
+use std::{time::Instant, net::SocketAddr};
+
+use crate::{osc::Command, patch::Patch, fade::Target, effects::Effects, scene::Scenes, cue::CueList, master::Masters, source::{Sources, SourceId, SourceConfig}, mapping::Mapping, safety::Safety, feedback::Feedback};
+
+pub struct Universe {
+  // merged values of all sources
+  data: Vec<u8>,
+  patch: Patch,
+  sources: Sources,
+  effects: Effects,
+  scenes: Scenes,
+  cues: CueList,
+  masters: Masters,
+  mapping: Mapping,
+  safety: Safety,
+  feedback: Feedback,
+  // logical frame before the mapping
+  logical: Vec<u8>,
+}
+
+impl Universe {
+  pub fn new(size: usize, patch: Patch, scenes: Scenes, cues: CueList, sources: SourceConfig, feedback: Feedback) -> Universe {
+    Universe {
+      data: vec![0; size],
+      masters: Masters::new(&patch, size),
+      sources: Sources::new(&patch, size, sources),
+      mapping: Mapping::new(&patch, size),
+      safety: Safety::new(&patch, size),
+      feedback,
+      logical: vec![0; size],
+      patch,
+      effects: Effects::new(),
+      scenes,
+      cues,
+    }
+  }
+  pub fn set_default_look(&mut self, scene: &str) -> bool {
+    match self.scenes.get(scene) {
+      Some(look) => {
+        self.sources.set_default_look(look);
+        true
+      },
+      None => false,
+    }
+  }
+  // Returns reply for the sender, if the command asks for one.
+  pub fn execute(&mut self, source: SourceId, cmd: Command) -> Option<rosc::OscMessage> {
+    match cmd {
+      Command::Set { address, values } => {
+        let stamp = self.sources.next_stamp();
+        let source = self.sources.get(source);
+        for (i, v) in values.into_iter().enumerate() {
+          if let Some(v) = v {
+            if self.safety.is_locked(i+address) {
+              continue;
+            }
+            source.set(i+address, v, stamp);
+          }
+        }
+      },
+      Command::Set16 { address, values } => {
+        let stamp = self.sources.next_stamp();
+        let source = self.sources.get(source);
+        for (i, v) in values.iter().enumerate() {
+          if let Some(v) = v {
+            let a = self.patch.attribute16(address + 2*i);
+            if self.safety.is_locked(a.coarse) || self.safety.is_locked(a.fine) {
+              continue;
+            }
+            source.set16(a, *v, stamp);
+          }
+        }
+      },
+      Command::Fade { address, values, time, profile } => {
+        let stamp = self.sources.next_stamp();
+        let source = self.sources.get(source);
+        for (i, v) in values.iter().enumerate() {
+          if let Some(v) = v {
+            if i+address < self.data.len() && !self.safety.is_locked(i+address) {
+              source.fade(Target::Channel(i+address), *v as f32, time, profile, stamp);
+            }
+          }
+        }
+      },
+      Command::Fade16 { address, values, time, profile } => {
+        let stamp = self.sources.next_stamp();
+        let source = self.sources.get(source);
+        for (i, v) in values.iter().enumerate() {
+          if let Some(v) = v {
+            let a = self.patch.attribute16(address + 2*i);
+            if self.safety.is_locked(a.coarse) || self.safety.is_locked(a.fine) {
+              continue;
+            }
+            source.fade(Target::Attribute16(a), *v as f32, time, profile, stamp);
+          }
+        }
+      },
+      Command::EffectDefine { name, effect } => self.effects.define(name, effect),
+      Command::EffectStart(name) => self.effects.start(&name),
+      Command::EffectStop(name) => self.effects.stop(&name),
+      Command::EffectRemove(name) => self.effects.remove(&name),
+      Command::SceneRecord(name) => self.scenes.record(&name, &self.data),
+      Command::SceneRecall { name, time } => {
+        match self.scenes.get(&name) {
+          Some(scene) => self.sources.recall(&self.data, scene, time, time),
+          None => println!("Scene {} not found", name),
+        }
+      },
+      Command::CueGo => self.cues.go(),
+      Command::CueBack => self.cues.back(),
+      Command::CueGoto(number) => if !self.cues.goto(number) {
+        println!("Cue {} not found", number);
+      },
+      // playback would mask the default look and every source of the same priority
+      Command::CueStop => {
+        self.cues.stop();
+        self.sources.release(SourceId::Playback);
+      },
+      Command::CueState => {
+        let (number, scene) = match self.cues.current() {
+          Some(c) => (c.number, c.scene.clone()),
+          None => (-1_f64, "".to_string()),
+        };
+        return Some(rosc::OscMessage {
+          addr: "/cue/state".to_string(),
+          args: vec![
+            rosc::OscType::Double(number),
+            rosc::OscType::String(scene),
+            rosc::OscType::String(self.cues.state().to_string()),
+          ],
+        });
+      },
+      Command::GrandMaster(level) => self.masters.set_grand(level),
+      Command::Submaster { name, level } => if !self.masters.set_sub(&name, level) {
+        println!("Submaster {} not found", name);
+      },
+      Command::Blackout(on) => self.masters.set_blackout(on),
+      // handled by the output loop
+      Command::Status | Command::Sync => {},
+      Command::Subscribe(port) => if let SourceId::Osc(addr) = source {
+        if !self.feedback.subscribe(SocketAddr::new(addr.ip(), port)) {
+          println!("Too many feedback subscribers, {} rejected", addr);
+        }
+      },
+      Command::Unsubscribe(port) => if let SourceId::Osc(addr) = source {
+        self.feedback.unsubscribe(SocketAddr::new(addr.ip(), port.unwrap_or(addr.port())));
+      },
+      Command::Limit { channel, min, max } => if !self.safety.set_limit(channel, min, max) {
+        println!("Bad limit {}-{} for channel {}", min, max, channel);
+      },
+      Command::Park { channel, value } => if !self.safety.set_parked(channel, value) {
+        println!("Can't park channel {}", channel);
+      },
+      Command::Lock { channel, locked } => if !self.safety.set_locked(channel, locked) {
+        println!("Can't lock channel {}", channel);
+      },
+      Command::Priority(priority) => self.sources.set_priority(source, priority),
+      Command::Release => self.sources.release(source),
+    }
+    None
+  }
+  // Advances running fades and renders output frame, called once per DMX frame.
+  pub fn render(&mut self, now: Instant, frame: &mut [u8]) {
+    if let Some(cue) = self.cues.tick(now) {
+      match self.scenes.get(&cue.scene) {
+        Some(scene) => self.sources.recall(&self.data, scene, cue.fade_in, cue.fade_out),
+        None => println!("Scene {} of cue {} not found", cue.scene, cue.number),
+      }
+    }
+    self.sources.update(now);
+    self.sources.merge(&mut self.data);
+    self.logical.copy_from_slice(&self.data);
+    self.effects.apply(&mut self.logical, now);
+    self.masters.apply(&mut self.logical);
+    self.mapping.apply(&self.logical, frame);
+    self.masters.apply_blackout(frame);
+    // parks and limits hold during blackout too
+    self.safety.apply(frame);
+  }
+  // Merged values of all sources, before effects, masters and mapping.
+  pub fn values(&self) -> &[u8] {
+    &self.data
+  }
+  pub fn is_blackout(&self) -> bool {
+    self.masters.is_blackout()
+  }
+  // Feedback packets for subscribed clients, if it's time to send them.
+  pub fn feedback(&mut self, now: Instant) -> Vec<(SocketAddr, rosc::OscPacket)> {
+    self.feedback.poll(&self.data, now)
+  }
+}